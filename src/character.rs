@@ -1,49 +1,189 @@
-use std::f64::consts::PI;
-
 use crate::prelude::*;
+use std::collections::BTreeMap;
 
 use self::spell::Species;
 
+/// Identifies a piece for as long as it exists, including across save files.
+pub type EntityId = usize;
+
+/// Stands in for the ID of a piece loaded from a save file that predates entity IDs,
+/// until `world::Manager::saved_payload` gives it a fresh one.
+pub const MISSING_ID: EntityId = EntityId::MAX;
+
+fn missing_id() -> EntityId {
+	MISSING_ID
+}
+
+/// Read a momentum either as an `OrdDir`, or as the angle in radians that older save files stored.
+fn deserialize_momentum<'de, D: serde::Deserializer<'de>>(
+	deserializer: D,
+) -> Result<OrdDir, D::Error> {
+	#[derive(serde::Deserialize)]
+	#[serde(untagged)]
+	enum Momentum {
+		Dir(OrdDir),
+		Angle(f64),
+	}
+	Ok(match serde::Deserialize::deserialize(deserializer)? {
+		Momentum::Dir(dir) => dir,
+		Momentum::Angle(angle) => OrdDir::from_angle(angle),
+	})
+}
+
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Piece {
 	// These are nice and serializable :)
+	/// Assigned by `world::Manager::spawn`, and never reused for another piece.
+	#[serde(default = "missing_id")]
+	pub id: EntityId,
 	pub species: Species,
 	pub x: i32,
 	pub y: i32,
 	pub z: i32,
-	pub momentum: f64,
+	/// The direction in which this piece last moved.
+	#[serde(deserialize_with = "deserialize_momentum")]
+	pub momentum: OrdDir,
+	/// Named counters, changed by axioms such as `IncrementCounter`.
+	#[serde(default)]
+	pub properties: BTreeMap<String, i32>,
+	/// The pieces making up this piece's body, nearest first.
+	/// Each one follows the previous positions of the one ahead of it.
+	#[serde(default)]
+	pub segments: Vec<EntityId>,
+	/// The route this piece walks on its own, one step per turn.
+	#[serde(default)]
+	pub patrol: Option<Patrol>,
 }
 
 impl Piece {
 	pub fn new(sheet: Sheet, resources: &ResourceManager) -> Self {
+		Self::default()
+	}
+
+	/// The value of the counter called `name`, which is 0 if it was never set.
+	pub fn counter(&self, name: &str) -> i32 {
+		self.properties.get(name).copied().unwrap_or_default()
+	}
+}
+
+impl Default for Piece {
+	fn default() -> Self {
 		Self {
+			id: 0,
 			species: Species::Wall,
 			x: 0,
 			y: 0,
 			z: 0,
-			momentum: PI / 2.,
+			momentum: OrdDir::Down,
+			properties: BTreeMap::new(),
+			segments: Vec::new(),
+			patrol: None,
 		}
 	}
 }
 
-#[derive(Copy, Clone, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
+/// A route followed by a piece, one step per turn. See `world::Manager::step_patrols`.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Patrol {
+	/// Walk to each of these tiles in turn, then start over. The first one is the next to be reached.
+	///
+	/// In vault files, they are relative to the vault's top-left corner.
+	Waypoints(Vec<(i32, i32)>),
+	/// Walk in this direction, turning around when blocked.
+	Bounce(OrdDir),
+}
+
+/// One of the eight directions a piece can face or move in.
+///
+/// Angles follow the screen: y grows downwards, so `Right` is 0 and `Down` is π/2.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize)]
 pub enum OrdDir {
 	Up,
+	UpRight,
 	Right,
+	DownRight,
 	Down,
+	DownLeft,
 	Left,
+	UpLeft,
 }
 
 impl OrdDir {
+	/// Every direction, clockwise from `Right`.
+	pub const ALL: [OrdDir; 8] = [
+		OrdDir::Right,
+		OrdDir::DownRight,
+		OrdDir::Down,
+		OrdDir::DownLeft,
+		OrdDir::Left,
+		OrdDir::UpLeft,
+		OrdDir::Up,
+		OrdDir::UpRight,
+	];
+	/// The directions without diagonals, clockwise from `Up`.
+	pub const ORTHOGONAL: [OrdDir; 4] = [OrdDir::Up, OrdDir::Right, OrdDir::Down, OrdDir::Left];
+
 	pub fn as_offset(self) -> (i32, i32) {
 		let (x, y) = match self {
 			OrdDir::Up => (0, -1),
+			OrdDir::UpRight => (1, -1),
 			OrdDir::Right => (1, 0),
+			OrdDir::DownRight => (1, 1),
 			OrdDir::Down => (0, 1),
+			OrdDir::DownLeft => (-1, 1),
 			OrdDir::Left => (-1, 0),
+			OrdDir::UpLeft => (-1, -1),
 		};
 		(x, y)
 	}
+
+	pub fn as_angle(self) -> f64 {
+		let (x, y) = self.as_offset();
+		f64::from(y).atan2(f64::from(x))
+	}
+
+	/// The direction closest to `angle`, in radians.
+	pub fn from_angle(angle: f64) -> Self {
+		let eighth = (angle / std::f64::consts::FRAC_PI_4).round() as i32;
+		Self::ALL[eighth.rem_euclid(8) as usize]
+	}
+
+	/// The direction that best approaches a tile `offset` tiles away, if it is not this tile.
+	pub fn from_offset(offset: (i32, i32)) -> Option<Self> {
+		let (x, y) = offset;
+		if (x, y) == (0, 0) {
+			None
+		} else {
+			Some(Self::from_angle(f64::from(y).atan2(f64::from(x))))
+		}
+	}
+
+	/// Like `from_offset`, but never diagonal.
+	pub fn orthogonal_from_offset(offset: (i32, i32)) -> Option<Self> {
+		let (x, y) = offset;
+		if (x, y) == (0, 0) {
+			None
+		} else if x.abs() >= y.abs() {
+			Some(if x > 0 { OrdDir::Right } else { OrdDir::Left })
+		} else {
+			Some(if y > 0 { OrdDir::Down } else { OrdDir::Up })
+		}
+	}
+
+	pub fn is_diagonal(self) -> bool {
+		let (x, y) = self.as_offset();
+		x != 0 && y != 0
+	}
+
+	/// Turn clockwise by `eighths` eighths of a full turn (45° each).
+	pub fn rotate_clockwise(self, eighths: i32) -> Self {
+		let index = Self::ALL.iter().position(|dir| *dir == self).unwrap() as i32;
+		Self::ALL[(index + eighths).rem_euclid(8) as usize]
+	}
+
+	pub fn opposite(self) -> Self {
+		self.rotate_clockwise(4)
+	}
 }
 
 /// Anything a character piece can "do".
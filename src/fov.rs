@@ -0,0 +1,84 @@
+use crate::world::{map_wrap, wrapped_offset, Manager, WORLD_COLS, WORLD_ROWS};
+
+/// Every tile on `origin`'s layer that can be seen from `origin`, within `radius` tiles.
+///
+/// Sight is blocked by occupied tiles, but the blocking piece itself is still seen.
+/// Lines of sight take the shortest way around the map, so they may cross its edges.
+/// `origin` itself is not included.
+pub fn visible_tiles(
+	manager: &Manager,
+	origin: (i32, i32, i32),
+	radius: usize,
+) -> Vec<(i32, i32, i32)> {
+	let origin = map_wrap(origin.0, origin.1, origin.2);
+	let radius = radius as i32;
+	// Only look at offsets within `radius`, and never reach the same tile twice around the map.
+	let (cols, rows) = (WORLD_COLS as i32, WORLD_ROWS as i32);
+	let (min_dx, max_dx) = ((-radius).max(-(cols - 1) / 2), radius.min(cols / 2));
+	let (min_dy, max_dy) = ((-radius).max(-(rows - 1) / 2), radius.min(rows / 2));
+	let mut visible = Vec::new();
+	for dy in min_dy..=max_dy {
+		for dx in min_dx..=max_dx {
+			if (dx, dy) == (0, 0) || dx * dx + dy * dy > radius * radius {
+				continue;
+			}
+			let tile = map_wrap(origin.0 + dx, origin.1 + dy, origin.2);
+			if line_of_sight(manager, origin, tile) {
+				visible.push(tile);
+			}
+		}
+	}
+	visible
+}
+
+/// Whether nothing stands between `from` and `to`.
+///
+/// Pieces standing on `from` or `to` do not count.
+/// The line takes the shortest way around the map, and tiles on different layers never see each other.
+pub fn line_of_sight(manager: &Manager, from: (i32, i32, i32), to: (i32, i32, i32)) -> bool {
+	if from.2 != to.2 {
+		return false;
+	}
+	let (dx, dy) = wrapped_offset(from, to);
+	let line = line_between_two_points(from, (from.0 + dx, from.1 + dy, from.2));
+	// The first and last points are `from` and `to`.
+	line.iter()
+		.skip(1)
+		.take(line.len().saturating_sub(2))
+		.all(|(x, y, z)| {
+			let (x, y, z) = map_wrap(*x, *y, *z);
+			manager.get_character_at(x, y, z).is_none()
+		})
+}
+
+/// Return all tiles crossed by a straight line from `start` to `end`, both included.
+///
+/// The points are not wrapped around the map.
+pub fn line_between_two_points(
+	start: (i32, i32, i32),
+	end: (i32, i32, i32),
+) -> Vec<(i32, i32, i32)> {
+	let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+	let (nx, ny) = (dx.abs(), dy.abs());
+	let (sign_x, sign_y) = (dx.signum(), dy.signum());
+
+	let mut p = (start.0, start.1, start.2);
+	let mut points = vec![p];
+	let mut ix = 0;
+	let mut iy = 0;
+	while ix < nx || iy < ny {
+		let step_x = (0.5 + ix as f32) / nx as f32;
+		let step_y = (0.5 + iy as f32) / ny as f32;
+		if step_x < step_y {
+			// next step is horizontal
+			p.0 += sign_x;
+			ix += 1;
+		} else {
+			// next step is vertical
+			p.1 += sign_y;
+			iy += 1;
+		}
+		points.push(p);
+	}
+	points
+}
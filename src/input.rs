@@ -1,13 +1,27 @@
 use crate::prelude::*;
-use sdl2::{
-	event::Event,
-	keyboard::{Keycode, Scancode},
-};
+use sdl2::{event::Event, keyboard::Scancode};
 
-use self::spell::{process_axioms, Species, Synapse};
+use self::replay::Action;
+use self::spell::{preview_keypress, trigger_keypress, Preview};
 
 pub enum Mode {
 	Normal,
+	/// Keypresses only show what they would do.
+	/// Pressing the previewed key a second time commits it.
+	Preview {
+		key: Option<String>,
+		preview: Preview,
+	},
+}
+
+impl Mode {
+	/// Forget the current preview, since it no longer matches the world.
+	fn clear_preview(&mut self) {
+		if let Mode::Preview { key, preview } = self {
+			*key = None;
+			*preview = Preview::default();
+		}
+	}
 }
 
 pub struct Result {
@@ -15,7 +29,13 @@ pub struct Result {
 	pub new_manager: Option<world::Manager>,
 }
 
-pub fn world(event_pump: &mut sdl2::EventPump, world_manager: &world::Manager) -> Result {
+pub fn world(
+	event_pump: &mut sdl2::EventPump,
+	world_manager: &world::Manager,
+	mode: &mut Mode,
+	options: &Options,
+	replay: &mut Replay,
+) -> Result {
 	let mut new_manager = None;
 	for event in event_pump.poll_iter() {
 		match event {
@@ -33,18 +53,47 @@ pub fn world(event_pump: &mut sdl2::EventPump, world_manager: &world::Manager) -
 				keycode: Some(keycode),
 				..
 			} => {
-				for axiom in &world_manager.characters {
-					let axiom = axiom.borrow();
-					let (x, y, z, species) = (axiom.x, axiom.y, axiom.z, &axiom.species);
-					if let Species::Keypress(key) = species {
-						if Keycode::from_name(key).unwrap() == keycode {
-							drop(axiom);
-							new_manager =
-								process_axioms(vec![Synapse::new(x, y, z)], world_manager)
-									.new_manager;
-						}
+				let turns = world_manager.turn_count.borrow().turns;
+				if options.controls.preview.contains(&(keycode as i32)) {
+					*mode = match mode {
+						Mode::Normal => Mode::Preview {
+							key: None,
+							preview: Preview::default(),
+						},
+						Mode::Preview { .. } => Mode::Normal,
+					};
+					continue;
+				}
+				if options.controls.trace.contains(&(keycode as i32)) {
+					let mut trace = world_manager.trace.borrow_mut();
+					*trace = if trace.is_some() {
+						None
+					} else {
+						Some(Vec::new())
+					};
+					continue;
+				}
+				if options.controls.undo.contains(&(keycode as i32)) {
+					replay.record(turns, Action::Undo);
+					new_manager = world_manager.rewind(1);
+					mode.clear_preview();
+					continue;
+				}
+				let key = keycode.name();
+				if let Mode::Preview {
+					key: previewed_key,
+					preview,
+				} = mode
+				{
+					if previewed_key.as_deref() != Some(&key) {
+						*preview = preview_keypress(world_manager, &key);
+						*previewed_key = Some(key);
+						continue;
 					}
+					mode.clear_preview();
 				}
+				replay.record(turns, Action::Keypress(key.clone()));
+				new_manager = trigger_keypress(world_manager, &key).new_manager;
 				//let mut next_character = world_manager.next_character().borrow_mut();
 			}
 			_ => {}
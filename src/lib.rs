@@ -9,12 +9,16 @@
 pub mod animation;
 pub mod character;
 pub mod console;
+pub mod fov;
 pub mod gui;
 pub mod input;
 pub mod item;
 pub mod nouns;
 pub mod options;
+pub mod pathfind;
+pub mod replay;
 pub mod resource_manager;
+pub mod simulation;
 pub mod spell;
 pub mod vault;
 pub mod world;
@@ -28,7 +32,9 @@ pub mod prelude {
 	pub use item::Item;
 	pub use nouns::Nouns;
 	pub use options::Options;
+	pub use replay::Replay;
 	pub use resource_manager::ResourceManager;
+	pub use simulation::Simulation;
 	pub use spell::Spell;
 	pub use vault::Vault;
 }
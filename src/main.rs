@@ -4,7 +4,6 @@ use sdltgfp::options::{RESOURCE_DIRECTORY, USER_DIRECTORY};
 use sdltgfp::prelude::*;
 use sdltgfp::spell::match_axiom_with_codename;
 use sdltgfp::world::{WORLD_COLS, WORLD_ROWS};
-use std::cell::RefCell;
 use std::f32::consts::PI;
 use std::process::exit;
 use tracing::*;
@@ -67,46 +66,31 @@ pub fn main() {
 		species: spell::Species::Terminal,
 		..character::Piece::new(resources.get_sheet("luvui").unwrap().clone(), &resources)
 	};
-	let player_piece = std::rc::Rc::new(RefCell::new(player));
-	let mut world_manager = world::Manager {
-		location: world::Location {
-			level: String::from("New Level"),
-			floor: 0,
-		},
-		console: Console::default(),
-		reality_anchor: player_piece.clone(),
+	let mut world_manager = world::Manager::new(player);
+	world_manager.save_slot = world::SaveSlot::File("save.toml".into());
 
-		current_level: world::Level::default(),
-		characters: Vec::new(),
-		effects: RefCell::new(Vec::new()),
-		turn_count: RefCell::new(world::TurnCounter { turns: 0 }),
-	};
-
-	if std::path::Path::new("save.toml").exists() {
-		let saved_chars = std::fs::read_to_string("save.toml").unwrap();
-		let saved_manager: world::SavePayload = toml::from_str(&saved_chars).unwrap();
-		world_manager.characters = saved_manager.characters.clone();
-		// Locate the player among the saved characters.
-		world_manager.reality_anchor = world_manager
-			.locate_player(&saved_manager)
-			.expect("The player did not exist in the save file")
-			.clone();
-		world_manager.turn_count = RefCell::new(world::TurnCounter {
-			turns: saved_manager.turn_count,
-		});
+	if let Some(saved_manager) = world_manager.saved_payload() {
+		world_manager.restore(&saved_manager);
 	} else {
-		world_manager.characters.push(player_piece);
 		//world_manager.characters.push(CharacterRef::new(ally));
-		world_manager.apply_vault(
-			0,
-			0,
-			0,
-			resources.get_vault("world_roots").unwrap(),
-			&resources,
-		);
-		world_manager.apply_vault(0, 0, 1, resources.get_vault("lower").unwrap(), &resources);
+		world_manager.apply_vault(0, 0, 0, resources.get_vault("world_roots").unwrap());
+		world_manager.apply_vault(0, 0, 1, resources.get_vault("lower").unwrap());
+	}
+	if std::env::args().any(|arg| arg == "--deferred") {
+		world_manager.effect_mode = spell::EffectMode::Deferred;
 	}
 
+	// Rebuild the world from a replay file, if one was passed with `--replay <path>`.
+	if let Some(path) = std::env::args().skip_while(|arg| arg != "--replay").nth(1) {
+		match Replay::open(&path) {
+			Ok(replay) => world_manager = replay.play(),
+			Err(msg) => error!("Failed to open replay {path}: {msg}"),
+		}
+	}
+	// Record every keypress from here on, so that bug reports can be reproduced.
+	let replay_path = USER_DIRECTORY.join("replay.toml");
+	let mut replay = Replay::new(&world_manager);
+
 	let mut spritesheet = resources.get_owned_texture("spritesheet").unwrap();
 	let font = ttf_context
 		.load_font_from_rwops(
@@ -119,7 +103,7 @@ pub fn main() {
 		.unwrap();
 
 	// Print some debug messages to test the console.
-	world_manager.console.print("Hello, world!");
+	world_manager.console.borrow_mut().print("Hello, world!");
 
 	// TODO: Display this on-screen.
 	let mut input_mode = input::Mode::Normal;
@@ -127,8 +111,20 @@ pub fn main() {
 	let mut zoom_amount = 0;
 	loop {
 		// Input processing
-		let input_result = input::world(&mut event_pump, &mut world_manager);
+		let recorded_inputs = replay.inputs.len();
+		let input_result = input::world(
+			&mut event_pump,
+			&world_manager,
+			&mut input_mode,
+			&options,
+			&mut replay,
+		);
 		let (exit, new_manager) = (input_result.exit, input_result.new_manager);
+		if replay.inputs.len() != recorded_inputs {
+			if let Err(msg) = replay.save(&replay_path) {
+				error!("Failed to save replay: {msg}");
+			}
+		}
 		if exit {
 			break;
 		};
@@ -140,7 +136,7 @@ pub fn main() {
 		// This is the only place where delta time should be used.
 		{
 			let delta = update_delta(&mut last_time, &mut current_time, &timer_subsystem);
-			world_manager.console.update(delta);
+			world_manager.console.borrow_mut().update(delta);
 		}
 
 		// Rendering
@@ -194,10 +190,9 @@ pub fn main() {
 			(-world_width, -world_height),
 		];
 
-		for character in world_manager.characters.iter() {
-			let character_ptr = character.as_ptr();
+		for character in world_manager.characters.borrow().iter() {
 			let character = character.borrow();
-			if character_ptr == world_manager.reality_anchor.as_ptr() {
+			if character.id == world_manager.reality_anchor.get() {
 				curr_xy = (character.x, character.y);
 				curr_z = character.z;
 			}
@@ -240,13 +235,13 @@ pub fn main() {
 		// 	}
 		// }
 		// Draw characters (normal)
-		for character in world_manager.characters.iter() {
-			let character_ptr = character.as_ptr();
+		for character in world_manager.characters.borrow().iter() {
 			let character = character.borrow();
+			let is_anchor = character.id == world_manager.reality_anchor.get();
 			if curr_z as i32 != character.z {
 				continue;
 			}
-			let (x, y) = if character_ptr == world_manager.reality_anchor.as_ptr() {
+			let (x, y) = if is_anchor {
 				curr_xy = (character.x, character.y);
 				(
 					((tiles_in_viewport / 2) * (options.ui.tile_size)) as i32,
@@ -260,29 +255,10 @@ pub fn main() {
 						* (zoom_amount + options.ui.tile_size as i32),
 				)
 			};
-			let mut texture_y = 0;
-			let texture_x = match character.species {
-				spell::Species::Wall => 3,
-				spell::Species::Terminal => 0,
-				spell::Species::WatchBot => 8,
-				spell::Species::EpsilonHead => 67,
-				spell::Species::PushCrate => 7,
-				spell::Species::EpsilonTail(_) => 68,
-				_ => {
-					// It could be an axiom.
-					let axiom_name = match_axiom_with_codename(&character.species);
-					if let Some(axiom_name) = axiom_name {
-						texture_y = 16;
-						resources.get_spell(axiom_name).unwrap().icon
-					} else {
-						// Fallback "missing texture" for unknown species.
-						1
-					}
-				}
-			} * 16;
+			let (texture_x, texture_y) = species_sprite(&character.species, &resources);
 			let source_rect = Rect::new(texture_x, texture_y, 16, 16);
 			for (off_x, off_y) in areas {
-				if character_ptr == world_manager.reality_anchor.as_ptr() {
+				if is_anchor {
 					// Prevent the main character from being drawn multiple times for the "looping world" effect.
 					if (off_x, off_y) != (0, 0) {
 						continue;
@@ -340,13 +316,126 @@ pub fn main() {
 			}
 			effect.alpha = effect.alpha.checked_sub(10).unwrap_or_default();
 		}
+		// Draw the ghost tiles of the previewed keypress
+		if let input::Mode::Preview { preview, .. } = &input_mode {
+			let mut ghosts = Vec::new();
+			for tar in &preview.targets {
+				ghosts.push((*tar, (13 * 16, 0), 96));
+			}
+			for tar in &preview.annihilations {
+				ghosts.push((*tar, (13 * 16, 0), 224));
+			}
+			for (from, to) in &preview.swaps {
+				ghosts.push((*from, (14 * 16, 0), 160));
+				ghosts.push((*to, (14 * 16, 0), 160));
+			}
+			for (tar, species) in preview.twins.iter().chain(&preview.pastes) {
+				ghosts.push((*tar, species_sprite(species, &resources), 128));
+			}
+			for teleport in &preview.teleports {
+				ghosts.push((
+					teleport.to,
+					species_sprite(&teleport.species, &resources),
+					128,
+				));
+			}
+			for ((ghost_x, ghost_y, ghost_z), (texture_x, texture_y), alpha) in ghosts {
+				if curr_z != ghost_z {
+					continue;
+				}
+				let (x, y) = (
+					(ghost_x - curr_xy.0 + wi_width as i32 / 2 / options.ui.tile_size as i32)
+						* (zoom_amount + options.ui.tile_size as i32),
+					(ghost_y - curr_xy.1 + wi_height as i32 / 2 / options.ui.tile_size as i32)
+						* (zoom_amount + options.ui.tile_size as i32),
+				);
+				let source_rect = Rect::new(texture_x, texture_y, 16, 16);
+				spritesheet.set_alpha_mod(alpha);
+				for (off_x, off_y) in areas {
+					canvas
+						.copy(
+							&spritesheet,
+							Some(source_rect),
+							Some(Rect::new(
+								off_x + x - zoom_amount * 16,
+								off_y + y - zoom_amount * 16,
+								options.ui.tile_size + zoom_amount as u32,
+								options.ui.tile_size + zoom_amount as u32,
+							)),
+						)
+						.unwrap();
+				}
+			}
+		}
 		// Restore opacity after effects loop.
 		spritesheet.set_alpha_mod(255);
+		// Draw the synapse trace overlay
+		if let Some(trace) = world_manager.trace.borrow().as_ref() {
+			let tile_size = zoom_amount + options.ui.tile_size as i32;
+			let screen_position = |(x, y, _): (i32, i32, i32)| {
+				(
+					(x - curr_xy.0 + wi_width as i32 / 2 / options.ui.tile_size as i32) * tile_size
+						- zoom_amount * 16,
+					(y - curr_xy.1 + wi_height as i32 / 2 / options.ui.tile_size as i32)
+						* tile_size - zoom_amount * 16,
+				)
+			};
+			let tile_center = |tile| {
+				let (x, y) = screen_position(tile);
+				(x + tile_size / 2, y + tile_size / 2)
+			};
+			// Lines across the map wrap would cross the entire screen.
+			let adjacent = |a: (i32, i32, i32), b: (i32, i32, i32)| {
+				(a.0 - b.0).abs() <= 1 && (a.1 - b.1).abs() <= 1
+			};
+			for step in trace.iter().filter(|step| step.pulse.2 == curr_z) {
+				canvas.set_draw_color(Color::RED);
+				for (_, targets) in &step.casters {
+					for tar in targets {
+						let (x, y) = screen_position(*tar);
+						canvas
+							.draw_rect(Rect::new(
+								x + tile_size / 4,
+								y + tile_size / 4,
+								tile_size as u32 / 2,
+								tile_size as u32 / 2,
+							))
+							.unwrap();
+					}
+				}
+				canvas.set_draw_color(Color::MAGENTA);
+				for (caster, _) in &step.casters {
+					let (x, y) = screen_position(*caster);
+					canvas
+						.draw_rect(Rect::new(x, y, tile_size as u32, tile_size as u32))
+						.unwrap();
+				}
+				canvas.set_draw_color(Color::YELLOW);
+				let (x, y) = screen_position(step.pulse);
+				canvas
+					.draw_rect(Rect::new(x, y, tile_size as u32, tile_size as u32))
+					.unwrap();
+				if let Some(next) = step.next.filter(|next| adjacent(step.pulse, *next)) {
+					canvas.set_draw_color(Color::GREEN);
+					canvas
+						.draw_line(tile_center(step.pulse), tile_center(next))
+						.unwrap();
+				}
+				canvas.set_draw_color(Color::CYAN);
+				for branch in &step.branches {
+					if adjacent(step.pulse, *branch) {
+						canvas
+							.draw_line(tile_center(step.pulse), tile_center(*branch))
+							.unwrap();
+					}
+				}
+			}
+		}
 		// Render User Interface
 		canvas.set_viewport(None);
 
 		// Draw Console
-		world_manager.console.draw(
+		world_manager.console.borrow().draw(
 			&mut canvas,
 			Rect::new(
 				(window_size.0 - options.ui.pamphlet_width) as i32,
@@ -455,6 +544,34 @@ fn pamphlet(
 	]);
 }
 
+/// Find the position of a species' sprite on the spritesheet, in pixels.
+fn species_sprite(species: &spell::Species, resources: &ResourceManager) -> (i32, i32) {
+	let mut texture_y = 0;
+	let texture_x = match species {
+		spell::Species::Wall => 3,
+		spell::Species::Terminal => 0,
+		spell::Species::WatchBot => 8,
+		spell::Species::EpsilonHead => 67,
+		spell::Species::PushCrate => 7,
+		spell::Species::EpsilonTail(_) => 68,
+		spell::Species::Mirror(spell::MirrorAxis::Horizontal) => 15,
+		spell::Species::Mirror(spell::MirrorAxis::Vertical) => 16,
+		spell::Species::Mirror(spell::MirrorAxis::Point) => 14,
+		_ => {
+			// It could be an axiom.
+			let axiom_name = match_axiom_with_codename(species);
+			if let Some(axiom_name) = axiom_name {
+				texture_y = 16;
+				resources.get_spell(axiom_name).unwrap().icon
+			} else {
+				// Fallback "missing texture" for unknown species.
+				1
+			}
+		}
+	} * 16;
+	(texture_x, texture_y)
+}
+
 fn get_chain_border(width: usize, height: usize) -> Vec<ChainIcon> {
 	let offset = (width as f32 / 2., height as f32 / 2.);
 	let points = (0..width).flat_map(|x| (0..height).map(move |y| (x, y)));
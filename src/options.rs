@@ -80,6 +80,12 @@ pub struct Controls {
 	pub right: Vec<KeycodeIndex>,
 	pub up: Vec<KeycodeIndex>,
 	pub down: Vec<KeycodeIndex>,
+	/// Rewind the world to the start of the previous turn.
+	pub undo: Vec<KeycodeIndex>,
+	/// Toggle preview mode, where keypresses only show what they would do.
+	pub preview: Vec<KeycodeIndex>,
+	/// Toggle synapse tracing, and the overlay that displays it.
+	pub trace: Vec<KeycodeIndex>,
 }
 
 impl Default for Controls {
@@ -90,6 +96,9 @@ impl Default for Controls {
 			right: vec![K::L as i32, K::Right as i32, K::Kp6 as i32],
 			up: vec![K::K as i32, K::Up as i32, K::Kp8 as i32],
 			down: vec![K::J as i32, K::Down as i32, K::Kp2 as i32],
+			undo: vec![K::Z as i32, K::Backspace as i32],
+			preview: vec![K::Tab as i32],
+			trace: vec![K::F3 as i32],
 		}
 	}
 }
@@ -0,0 +1,90 @@
+use crate::character::OrdDir;
+use crate::world::{map_wrap, wrapped_offset, Manager, WORLD_COLS, WORLD_ROWS};
+use std::collections::VecDeque;
+
+/// Find the first step of the shortest route leading from `from` to the side of any of `goals`.
+///
+/// Routes stay on `from`'s layer, may cross the map's edges, and never pass through occupied tiles.
+/// Goals are usually pieces themselves, so standing next to one counts as having arrived.
+///
+/// Returns `None` if `from` is already next to a goal.
+/// If no goal can be reached at all, the free neighbour closest to a goal is returned instead,
+/// so that chasers still get as close as they can.
+pub fn first_step(
+	manager: &Manager,
+	from: (i32, i32, i32),
+	goals: &[(i32, i32, i32)],
+) -> Option<(i32, i32, i32)> {
+	let from = map_wrap(from.0, from.1, from.2);
+	let goals: Vec<(i32, i32, i32)> = goals
+		.iter()
+		.map(|goal| map_wrap(goal.0, goal.1, goal.2))
+		.collect();
+	let arrived = |tile: (i32, i32, i32)| {
+		neighbours(tile)
+			.iter()
+			.any(|neighbour| goals.contains(neighbour))
+	};
+	if arrived(from) {
+		return None;
+	}
+
+	// Each visited tile remembers the first step taken on the way to it.
+	let index = |(x, y, _): (i32, i32, i32)| y as usize * WORLD_COLS + x as usize;
+	let mut first_steps = vec![None; WORLD_ROWS * WORLD_COLS];
+	let mut queue = VecDeque::new();
+	first_steps[index(from)] = Some(from);
+	for neighbour in neighbours(from) {
+		if manager
+			.get_character_at(neighbour.0, neighbour.1, neighbour.2)
+			.is_none()
+			&& first_steps[index(neighbour)].is_none()
+		{
+			first_steps[index(neighbour)] = Some(neighbour);
+			queue.push_back(neighbour);
+		}
+	}
+	while let Some(tile) = queue.pop_front() {
+		let step = first_steps[index(tile)];
+		if arrived(tile) {
+			return step;
+		}
+		for neighbour in neighbours(tile) {
+			if first_steps[index(neighbour)].is_none()
+				&& manager
+					.get_character_at(neighbour.0, neighbour.1, neighbour.2)
+					.is_none()
+			{
+				first_steps[index(neighbour)] = step;
+				queue.push_back(neighbour);
+			}
+		}
+	}
+
+	// There is no way through, settle for the free neighbour closest to a goal.
+	neighbours(from)
+		.into_iter()
+		.filter(|(x, y, z)| manager.get_character_at(*x, *y, *z).is_none())
+		.min_by_key(|neighbour| {
+			goals
+				.iter()
+				.map(|goal| wrapped_distance(*neighbour, *goal))
+				.min()
+				.unwrap_or(i32::MAX)
+		})
+		.filter(|_| !goals.is_empty())
+}
+
+/// The number of orthogonal steps between `a` and `b`, taking the shortest way around the map.
+/// Layers are ignored.
+pub fn wrapped_distance(a: (i32, i32, i32), b: (i32, i32, i32)) -> i32 {
+	let (dx, dy) = wrapped_offset(a, b);
+	dx.abs() + dy.abs()
+}
+
+fn neighbours((x, y, z): (i32, i32, i32)) -> [(i32, i32, i32); 4] {
+	OrdDir::ORTHOGONAL.map(|dir| {
+		let (dx, dy) = dir.as_offset();
+		map_wrap(x + dx, y + dy, z)
+	})
+}
@@ -0,0 +1,149 @@
+use crate::prelude::*;
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+use std::{fs, io};
+use tracing::warn;
+
+/// A keypress that reached `input::world`.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Input {
+	/// The value of the turn counter when the key was pressed.
+	/// This is only used to detect desyncs during playback.
+	pub turn: usize,
+	pub action: Action,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub enum Action {
+	/// The key's name, as stored in `Species::Keypress`.
+	Keypress(String),
+	/// The player's undo key, which is bound in the options rather than by an axiom.
+	Undo,
+}
+
+/// A recording of a play session, which can be played back to rebuild the exact same `world::Manager`.
+///
+/// The axiom engine has no source of randomness,
+/// so the starting state, the save it could load and the keypresses are all that's needed.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Replay {
+	pub start: world::SavePayload,
+	/// What `LoadGame` would have loaded when the recording started.
+	/// Playback keeps its saves in memory, starting from this one.
+	#[serde(default)]
+	pub save: Option<world::SavePayload>,
+	pub inputs: Vec<Input>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+	#[error(transparent)]
+	Io(#[from] io::Error),
+	#[error(transparent)]
+	Deserialize(#[from] toml::de::Error),
+	#[error(transparent)]
+	Serialize(#[from] toml::ser::Error),
+}
+
+impl Replay {
+	/// Start a new recording from the current state of `manager`.
+	pub fn new(manager: &world::Manager) -> Self {
+		Self {
+			start: manager.save_payload(),
+			save: manager.saved_payload(),
+			inputs: Vec::new(),
+		}
+	}
+
+	/// # Errors
+	///
+	/// Fails if the file could not be opened or parsed.
+	pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+		Ok(toml::from_str(&fs::read_to_string(path)?)?)
+	}
+
+	/// # Errors
+	///
+	/// Fails if the replay could not be serialized or written.
+	pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+		fs::write(path, toml::to_string(self)?)?;
+		Ok(())
+	}
+
+	pub fn record(&mut self, turn: usize, action: Action) {
+		self.inputs.push(Input { turn, action });
+	}
+
+	/// Rebuild the world by pressing every recorded key, in order, starting from `start`.
+	///
+	/// This never reads or writes the player's save file.
+	pub fn play(&self) -> world::Manager {
+		let mut simulation = Simulation::new();
+		simulation.manager.restore(&self.start);
+		simulation.manager.save_slot =
+			world::SaveSlot::Memory(Rc::new(RefCell::new(self.save.clone())));
+		for input in &self.inputs {
+			if simulation.turns() != input.turn {
+				warn!(
+					"replay desync: {:?} happened on turn {}, but the world is on turn {}",
+					input.action,
+					input.turn,
+					simulation.turns()
+				);
+			}
+			match &input.action {
+				Action::Keypress(key) => simulation.press(key),
+				Action::Undo => simulation.undo(),
+			}
+		}
+		simulation.manager
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::simulation::tests::SAVE_AND_LOAD;
+
+	fn anchor_x(manager: &world::Manager) -> i32 {
+		manager.get_reality_anchor().borrow().x
+	}
+
+	fn saved_anchor_x(manager: &world::Manager) -> i32 {
+		let saved = manager.saved_payload().unwrap();
+		let anchor = saved
+			.characters
+			.iter()
+			.find(|piece| piece.borrow().id == saved.reality_anchor)
+			.unwrap()
+			.borrow()
+			.x;
+		anchor
+	}
+
+	#[test]
+	fn playback_uses_its_own_save() {
+		let mut simulation = Simulation::new();
+		simulation.load_vault(10, 10, 0, SAVE_AND_LOAD).unwrap();
+		simulation.press("S");
+		let mut replay = Replay::new(&simulation.manager);
+		for key in ["Right", "Right", "L", "Right", "S"] {
+			replay.record(simulation.turns(), Action::Keypress(key.into()));
+			simulation.press(key);
+		}
+		assert_eq!(anchor_x(&simulation.manager), 1);
+		assert_eq!(saved_anchor_x(&simulation.manager), 1);
+
+		// The live save changes after the recording, but playback loads the one it started with.
+		simulation.press("Right");
+		simulation.press("S");
+		let played = replay.play();
+		assert_eq!(anchor_x(&played), 1);
+		// Loading went back to the turn of the recording's own save.
+		assert_eq!(played.turn_count.borrow().turns, 1);
+		assert_eq!(saved_anchor_x(&played), 1);
+		// Saving during playback did not touch the live save.
+		assert_eq!(saved_anchor_x(&simulation.manager), 2);
+	}
+}
@@ -0,0 +1,1105 @@
+use crate::prelude::*;
+
+use self::spell::{increment_turn, trigger_keypress, Species};
+
+/// Drives the axiom engine without a window, an event pump or any textures.
+///
+/// This is meant for puzzle regression tests and tools that need to run on machines with no display.
+/// Everything the game does in response to a keypress goes through the same functions `input::world` uses.
+#[derive(Clone, Debug)]
+pub struct Simulation {
+	pub manager: world::Manager,
+}
+
+impl Default for Simulation {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Simulation {
+	/// Create an empty world containing only a `Terminal` reality anchor at the origin.
+	pub fn new() -> Self {
+		let player = character::Piece {
+			species: Species::Terminal,
+			..Default::default()
+		};
+		Self::from_manager(world::Manager::new(player))
+	}
+
+	pub fn from_manager(manager: world::Manager) -> Self {
+		Self { manager }
+	}
+
+	pub fn apply_vault(&mut self, x: i32, y: i32, z: i32, vault: &Vault) {
+		self.manager.apply_vault(x, y, z, vault);
+	}
+
+	/// Parse the contents of a `.vault` file and spawn it at the given coordinates.
+	///
+	/// # Errors
+	///
+	/// Returns an error if the vault could not be parsed.
+	pub fn load_vault(
+		&mut self,
+		x: i32,
+		y: i32,
+		z: i32,
+		vault_text: &str,
+	) -> Result<(), vault::Error> {
+		let vault = Vault::parse(vault_text)?;
+		self.apply_vault(x, y, z, &vault);
+		Ok(())
+	}
+
+	/// Press the key called `key`, as stored in `Species::Keypress`.
+	pub fn press(&mut self, key: &str) {
+		let result = trigger_keypress(&self.manager, key);
+		self.apply(result);
+	}
+
+	/// Advance the world by one turn, as if a `TurnIncrementer` had been reached.
+	pub fn step(&mut self) {
+		self.manager.record_history();
+		let result = increment_turn(&self.manager);
+		self.apply(result);
+	}
+
+	/// Rewind the world to the start of the previous turn, like the player's undo key.
+	pub fn undo(&mut self) {
+		if let Some(rewound) = self.manager.rewind(1) {
+			self.manager = rewound;
+		}
+	}
+
+	pub fn turns(&self) -> usize {
+		self.manager.turn_count.borrow().turns
+	}
+
+	fn apply(&mut self, result: spell::Result) {
+		if let Some(new_manager) = result.new_manager {
+			self.manager = new_manager;
+		}
+	}
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+
+	/// Moves the reality anchor one tile to the right when "Right" is pressed, then ends the turn.
+	const MOVE_RIGHT: &str = r#"
+[symbols."^"]
+Keypress = "Right"
+[symbols."P"]
+SelectRealityAnchor = []
+[symbols."E"]
+CardinalTargeter = "Right"
+[symbols."T"]
+Teleport = []
+[symbols."X"]
+TurnIncrementer = []
+
+# Layout
+^
+P
+E
+T
+X
+"#;
+
+	/// `MOVE_RIGHT`, with "S" saving the game and "L" loading it.
+	pub(crate) const SAVE_AND_LOAD: &str = r#"
+[symbols."^"]
+Keypress = "Right"
+[symbols."P"]
+SelectRealityAnchor = []
+[symbols."E"]
+CardinalTargeter = "Right"
+[symbols."T"]
+Teleport = []
+[symbols."X"]
+TurnIncrementer = []
+[symbols."S"]
+Keypress = "S"
+[symbols."%"]
+SaveGame = []
+[symbols."L"]
+Keypress = "L"
+[symbols."&"]
+LoadGame = []
+
+# Layout
+^ S L
+P % &
+E
+T
+X
+"#;
+
+	/// `MOVE_RIGHT`, with "B" rewinding two turns.
+	const REWIND: &str = r#"
+[symbols."^"]
+Keypress = "Right"
+[symbols."P"]
+SelectRealityAnchor = []
+[symbols."E"]
+CardinalTargeter = "Right"
+[symbols."T"]
+Teleport = []
+[symbols."X"]
+TurnIncrementer = []
+[symbols."B"]
+Keypress = "B"
+[symbols."R"]
+Rewind = 2
+
+# Layout
+^ B
+P R
+E
+T
+X
+"#;
+
+	/// A wall right of the reality anchor, with "D" making the anchor dash away from it
+	/// and "A" trying to annihilate everything around it.
+	/// Meant to be spawned at (1, 0, 0).
+	const DASH: &str = r##"
+[symbols."#"]
+Wall = []
+[symbols."D"]
+Keypress = "D"
+[symbols."A"]
+Keypress = "A"
+[symbols."P"]
+SelectRealityAnchor = []
+[symbols."+"]
+PlusTargeter = []
+[symbols."="]
+Dash = []
+[symbols."@"]
+SelfTargeter = []
+[symbols."x"]
+Annihilate = []
+
+# Layout
+#    D A
+     P P
+     + @
+     = x
+"##;
+
+	/// "D" moves the reality anchor one tile to the right, two turns later.
+	const DELAY: &str = r#"
+[symbols."^"]
+Keypress = "D"
+[symbols."P"]
+SelectRealityAnchor = []
+[symbols."Z"]
+Delay = 2
+[symbols."E"]
+CardinalTargeter = "Right"
+[symbols."T"]
+Teleport = []
+
+# Layout
+^
+P
+Z
+E
+T
+"#;
+
+	/// `MOVE_RIGHT`, with a pressure plate that pushes on anything entering (1, 0, 0)
+	/// one more tile to the right, when spawned at (10, 10, 0).
+	const PRESSURE_PLATE: &str = r#"
+[symbols."^"]
+Keypress = "Right"
+[symbols."P"]
+SelectRealityAnchor = []
+[symbols."E"]
+CardinalTargeter = "Right"
+[symbols."T"]
+Teleport = []
+[symbols."X"]
+TurnIncrementer = []
+[symbols."O"]
+OnEnterTile = [-11, -10, 0]
+
+# Layout
+^ O
+P E
+E T
+T
+X
+"#;
+
+	/// `MOVE_RIGHT`, with "M" summoning a wall to the right of the reality anchor.
+	const SUMMON: &str = r#"
+[symbols."^"]
+Keypress = "Right"
+[symbols."P"]
+SelectRealityAnchor = []
+[symbols."E"]
+CardinalTargeter = "Right"
+[symbols."T"]
+Teleport = []
+[symbols."X"]
+TurnIncrementer = []
+[symbols."M"]
+Keypress = "M"
+[symbols."S"]
+Summon = "Wall"
+
+# Layout
+^ M
+P P
+E E
+T S
+X
+"#;
+
+	/// "Right" moves every `EpsilonHead` one tile to the right,
+	/// and "M" summons a first segment to the left of each of them.
+	const MOVE_HEAD: &str = r#"
+[symbols."^"]
+Keypress = "Right"
+[symbols."M"]
+Keypress = "M"
+[symbols."H"]
+SelectSpecies = "EpsilonHead"
+[symbols."E"]
+CardinalTargeter = "Right"
+[symbols."L"]
+CardinalTargeter = "Left"
+[symbols."T"]
+Teleport = []
+[symbols."S"]
+Summon = { EpsilonTail = 1 }
+
+# Layout
+^ M
+H H
+E L
+T S
+"#;
+
+	/// A head whose two segments come before it, so they are spawned first.
+	const BODY: &str = r#"
+[symbols."!"]
+EpsilonHead = []
+[symbols."1"]
+EpsilonTail = 1
+[symbols."2"]
+EpsilonTail = 2
+
+# Layout
+12
+!
+"#;
+
+	/// A `WatchBot` that saves the game when it spots the reality anchor.
+	/// Meant to be spawned on the anchor's row.
+	const WATCH: &str = r#"
+[symbols."W"]
+WatchBot = []
+[symbols."O"]
+OnAnchorSpotted = []
+[symbols."S"]
+SaveGame = []
+
+# Layout
+W  O
+   S
+"#;
+
+	/// A `WatchBot` walking back and forth between its starting tile and two tiles to its right.
+	const PATROL: &str = r#"
+[symbols."W"]
+WatchBot = []
+
+[patrols."W"]
+Waypoints = [[2, 0], [0, 0]]
+
+# Layout
+W
+"#;
+
+	/// "Right" steps the reality anchor to the right without ending the turn,
+	/// and "J" teleports it two tiles further down.
+	/// A vertical `Mirror` stands four tiles to the right of the keys.
+	const MIRROR: &str = r#"
+[symbols."^"]
+Keypress = "Right"
+[symbols."J"]
+Keypress = "J"
+[symbols."P"]
+SelectRealityAnchor = []
+[symbols."E"]
+CardinalTargeter = "Right"
+[symbols."D"]
+SpecificCoord = [0, 2, 0]
+[symbols."T"]
+Teleport = []
+[symbols."M"]
+Mirror = "Vertical"
+
+# Layout
+^ J M
+P P
+E D
+T T
+"#;
+
+	/// A simulation with `vault` spawned far from the reality anchor, which stands on the origin.
+	fn simulation(vault: &str) -> Simulation {
+		let mut simulation = Simulation::new();
+		simulation.load_vault(10, 10, 0, vault).unwrap();
+		simulation
+	}
+
+	fn anchor_position(simulation: &Simulation) -> (i32, i32, i32) {
+		let anchor = simulation.manager.get_reality_anchor();
+		let anchor = anchor.borrow();
+		(anchor.x, anchor.y, anchor.z)
+	}
+
+	#[test]
+	fn keypress_moves_the_anchor() {
+		let mut simulation = simulation(MOVE_RIGHT);
+		simulation.press("Right");
+		assert_eq!(anchor_position(&simulation), (1, 0, 0));
+		assert_eq!(simulation.turns(), 1);
+		simulation.press("right");
+		assert_eq!(anchor_position(&simulation), (2, 0, 0));
+		assert_eq!(simulation.turns(), 2);
+	}
+
+	#[test]
+	fn unbound_keys_do_nothing() {
+		let mut simulation = simulation(MOVE_RIGHT);
+		simulation.press("Left");
+		assert_eq!(anchor_position(&simulation), (0, 0, 0));
+		assert_eq!(simulation.turns(), 0);
+	}
+
+	#[test]
+	fn step_only_advances_the_turn() {
+		let mut simulation = simulation(MOVE_RIGHT);
+		simulation.step();
+		simulation.step();
+		assert_eq!(anchor_position(&simulation), (0, 0, 0));
+		assert_eq!(simulation.turns(), 2);
+	}
+
+	#[test]
+	fn saves_are_kept_in_memory() {
+		let mut simulation = simulation(SAVE_AND_LOAD);
+		assert!(simulation.manager.saved_payload().is_none());
+		simulation.press("S");
+		simulation.press("Right");
+		assert_eq!(anchor_position(&simulation), (1, 0, 0));
+		simulation.press("L");
+		assert_eq!(anchor_position(&simulation), (0, 0, 0));
+		assert_eq!(simulation.turns(), 0);
+	}
+
+	#[test]
+	fn undo_goes_back_one_turn() {
+		let mut simulation = simulation(MOVE_RIGHT);
+		simulation.press("Right");
+		simulation.press("Right");
+		simulation.undo();
+		assert_eq!(anchor_position(&simulation), (1, 0, 0));
+		assert_eq!(simulation.turns(), 1);
+		// The undone turn can be played again.
+		simulation.press("Right");
+		assert_eq!(anchor_position(&simulation), (2, 0, 0));
+		assert_eq!(simulation.turns(), 2);
+	}
+
+	#[test]
+	fn rewind_axiom_goes_back_several_turns() {
+		let mut simulation = simulation(REWIND);
+		for _ in 0..3 {
+			simulation.press("Right");
+		}
+		simulation.press("B");
+		assert_eq!(anchor_position(&simulation), (1, 0, 0));
+		assert_eq!(simulation.turns(), 1);
+	}
+
+	#[test]
+	fn preview_shows_slides_without_moving_anything() {
+		let mut simulation = Simulation::new();
+		simulation.load_vault(1, 0, 0, DASH).unwrap();
+		let preview = spell::preview_keypress(&simulation.manager, "D");
+		assert_eq!(anchor_position(&simulation), (0, 0, 0));
+		let [teleport] = preview.teleports.as_slice() else {
+			panic!("expected a single move, got {:?}", preview.teleports);
+		};
+		assert_eq!((teleport.from, teleport.to), ((0, 0, 0), (9, 0, 0)));
+		simulation.press("D");
+		assert_eq!(anchor_position(&simulation), teleport.to);
+	}
+
+	#[test]
+	fn preview_leaves_out_the_anchor_annihilation() {
+		let mut simulation = Simulation::new();
+		simulation.load_vault(1, 0, 0, DASH).unwrap();
+		let preview = spell::preview_keypress(&simulation.manager, "A");
+		assert!(preview.annihilations.is_empty());
+		simulation.press("A");
+		assert_eq!(anchor_position(&simulation), (0, 0, 0));
+	}
+
+	#[test]
+	fn unreadable_save_files_are_ignored() {
+		let mut simulation = simulation(SAVE_AND_LOAD);
+		let path =
+			std::env::temp_dir().join(format!("sdltgfp-unreadable-{}.toml", std::process::id()));
+		std::fs::write(&path, "reality_anchor = { species = \"Terminal\" }").unwrap();
+		simulation.manager.save_slot = world::SaveSlot::File(path.clone());
+		assert!(simulation.manager.saved_payload().is_none());
+		simulation.press("Right");
+		simulation.press("L");
+		std::fs::remove_file(&path).unwrap();
+		assert_eq!(anchor_position(&simulation), (1, 0, 0));
+	}
+
+	#[test]
+	fn pieces_saved_without_ids_get_fresh_ones() {
+		let simulation = simulation(MOVE_RIGHT);
+		let saved = toml::to_string(&simulation.manager.save_payload()).unwrap();
+		assert!(saved.contains("id = 1\n") && saved.contains("id = 2\n"));
+		let path = std::env::temp_dir().join(format!("sdltgfp-no-ids-{}.toml", std::process::id()));
+		std::fs::write(&path, saved.replace("id = 1\n", "").replace("id = 2\n", "")).unwrap();
+		let mut manager = simulation.manager.clone();
+		manager.save_slot = world::SaveSlot::File(path.clone());
+		let payload = manager.saved_payload().unwrap();
+		std::fs::remove_file(&path).unwrap();
+		let mut ids: Vec<_> = payload.characters.iter().map(|p| p.borrow().id).collect();
+		ids.sort();
+		ids.dedup();
+		assert_eq!(ids.len(), payload.characters.len());
+		manager.restore(&payload);
+		assert_eq!(anchor_position(&Simulation { manager }), (0, 0, 0));
+	}
+
+	#[test]
+	fn momentum_saved_as_an_angle_still_loads() {
+		let simulation = simulation(MOVE_RIGHT);
+		let saved = toml::to_string(&simulation.manager.save_payload()).unwrap();
+		assert!(saved.contains("momentum = \"Down\""));
+		let saved = saved.replace("momentum = \"Down\"", "momentum = 1.5707963267948966");
+		let payload: world::SavePayload = toml::from_str(&saved).unwrap();
+		assert!(payload
+			.characters
+			.iter()
+			.all(|p| p.borrow().momentum == character::OrdDir::Down));
+	}
+
+	#[test]
+	fn delay_resumes_the_synapse_turns_later() {
+		let mut simulation = simulation(DELAY);
+		simulation.press("D");
+		assert_eq!(anchor_position(&simulation), (0, 0, 0));
+		simulation.step();
+		assert_eq!(anchor_position(&simulation), (0, 0, 0));
+		simulation.step();
+		assert_eq!(anchor_position(&simulation), (1, 0, 0));
+		simulation.step();
+		assert_eq!(anchor_position(&simulation), (1, 0, 0));
+	}
+
+	#[test]
+	fn entering_a_watched_tile_fires_its_contingency() {
+		let mut simulation = simulation(PRESSURE_PLATE);
+		simulation.press("Right");
+		assert_eq!(anchor_position(&simulation), (2, 0, 0));
+		// Only the watched tile fires it.
+		simulation.press("Right");
+		assert_eq!(anchor_position(&simulation), (3, 0, 0));
+	}
+
+	#[test]
+	fn summon_fills_empty_targets() {
+		let mut simulation = simulation(SUMMON);
+		let characters = simulation.manager.characters.borrow().len();
+		simulation.press("M");
+		let wall = simulation.manager.get_character_at(1, 0, 0).unwrap();
+		assert_eq!(wall.borrow().species, spell::Species::Wall);
+		// The tile is taken now, so nothing more is summoned there.
+		simulation.press("M");
+		assert_eq!(simulation.manager.characters.borrow().len(), characters + 1);
+		simulation.press("Right");
+		assert_eq!(anchor_position(&simulation), (0, 0, 0));
+	}
+
+	#[test]
+	fn moving_into_a_row_of_crates_pushes_all_of_them() {
+		let mut simulation = simulation(MOVE_RIGHT);
+		simulation
+			.load_vault(1, 0, 0, "[symbols.\"C\"]\nPushCrate = []\n\n# Layout\nCC\n")
+			.unwrap();
+		simulation.press("Right");
+		assert_eq!(anchor_position(&simulation), (1, 0, 0));
+		for x in [2, 3] {
+			let crate_piece = simulation.manager.get_character_at(x, 0, 0).unwrap();
+			assert_eq!(crate_piece.borrow().species, spell::Species::PushCrate);
+		}
+	}
+
+	#[test]
+	fn a_blocked_row_of_crates_does_not_move() {
+		let mut simulation = simulation(MOVE_RIGHT);
+		simulation
+			.load_vault(
+				1,
+				0,
+				0,
+				"[symbols.\"C\"]\nPushCrate = []\n[symbols.\"W\"]\nWall = []\n\n# Layout\nCCW\n",
+			)
+			.unwrap();
+		simulation.press("Right");
+		assert_eq!(anchor_position(&simulation), (0, 0, 0));
+		for x in [1, 2] {
+			let crate_piece = simulation.manager.get_character_at(x, 0, 0).unwrap();
+			assert_eq!(crate_piece.borrow().species, spell::Species::PushCrate);
+		}
+	}
+
+	fn position_of(simulation: &Simulation, species: Species) -> (i32, i32, i32) {
+		let piece = simulation.manager.get_characters_of_species(species)[0].clone();
+		let piece = piece.borrow();
+		(piece.x, piece.y, piece.z)
+	}
+
+	#[test]
+	fn segments_link_in_any_order_and_follow_their_head() {
+		let mut simulation = simulation(MOVE_HEAD);
+		simulation.load_vault(3, 3, 0, BODY).unwrap();
+		let head = simulation
+			.manager
+			.get_characters_of_species(Species::EpsilonHead)[0]
+			.clone();
+		assert_eq!(head.borrow().segments.len(), 2);
+		simulation.press("Right");
+		assert_eq!(position_of(&simulation, Species::EpsilonHead), (4, 4, 0));
+		assert_eq!(position_of(&simulation, Species::EpsilonTail(1)), (3, 4, 0));
+		assert_eq!(position_of(&simulation, Species::EpsilonTail(2)), (3, 3, 0));
+	}
+
+	#[test]
+	fn heads_gather_segments_across_the_map_edge() {
+		let mut simulation = simulation(MOVE_HEAD);
+		// The segments are already waiting on the other side of the edge when the head arrives.
+		let tail =
+			"[symbols.\"1\"]\nEpsilonTail = 1\n[symbols.\"2\"]\nEpsilonTail = 2\n\n# Layout\n21\n";
+		simulation
+			.load_vault(world::WORLD_COLS as i32 - 2, 3, 0, tail)
+			.unwrap();
+		simulation
+			.load_vault(
+				0,
+				3,
+				0,
+				"[symbols.\"!\"]\nEpsilonHead = []\n\n# Layout\n!\n",
+			)
+			.unwrap();
+		let head = simulation
+			.manager
+			.get_characters_of_species(Species::EpsilonHead)[0]
+			.clone();
+		assert_eq!(head.borrow().segments.len(), 2);
+	}
+
+	#[test]
+	fn summoned_segments_join_the_body_next_to_them() {
+		let mut simulation = simulation(MOVE_HEAD);
+		simulation
+			.load_vault(
+				3,
+				3,
+				0,
+				"[symbols.\"!\"]\nEpsilonHead = []\n\n# Layout\n!\n",
+			)
+			.unwrap();
+		simulation.press("M");
+		assert_eq!(position_of(&simulation, Species::EpsilonTail(1)), (2, 3, 0));
+		simulation.press("Right");
+		assert_eq!(position_of(&simulation, Species::EpsilonHead), (4, 3, 0));
+		assert_eq!(position_of(&simulation, Species::EpsilonTail(1)), (3, 3, 0));
+	}
+
+	#[test]
+	fn patrols_walk_between_their_waypoints() {
+		let mut simulation = simulation(PATROL);
+		let mut route = Vec::new();
+		for _ in 0..4 {
+			simulation.step();
+			route.push(position_of(&simulation, Species::WatchBot).0);
+		}
+		assert_eq!(route, vec![11, 12, 11, 10]);
+	}
+
+	#[test]
+	fn watchbots_spot_the_anchor_unless_something_is_in_the_way() {
+		let mut simulation = Simulation::new();
+		simulation.load_vault(2, 0, 0, WATCH).unwrap();
+		simulation.step();
+		assert!(simulation.manager.saved_payload().is_some());
+
+		let mut simulation = Simulation::new();
+		simulation.load_vault(2, 0, 0, WATCH).unwrap();
+		simulation
+			.load_vault(1, 0, 0, "[symbols.\"W\"]\nWall = []\n\n# Layout\nW\n")
+			.unwrap();
+		simulation.step();
+		assert!(simulation.manager.saved_payload().is_none());
+	}
+
+	#[test]
+	fn boxed_in_patrols_wait() {
+		let mut simulation = simulation(
+			r##"
+[symbols."#"]
+Wall = []
+[symbols."W"]
+WatchBot = []
+
+[patrols."W"]
+Waypoints = [[3, 1]]
+
+# Layout
+ #
+#W#
+ #
+"##,
+		);
+		simulation.step();
+		assert_eq!(position_of(&simulation, Species::WatchBot), (11, 11, 0));
+	}
+
+	#[test]
+	fn mirrors_repeat_the_anchor_steps_as_they_happen() {
+		let mut simulation = simulation(MIRROR);
+		simulation.press("Right");
+		assert_eq!(simulation.turns(), 0);
+		assert_eq!(
+			position_of(&simulation, Species::Mirror(spell::MirrorAxis::Vertical)),
+			(13, 10, 0)
+		);
+		// Teleports are not steps, so they are not mirrored.
+		simulation.press("J");
+		assert_eq!(anchor_position(&simulation), (0, 2, 0));
+		assert_eq!(
+			position_of(&simulation, Species::Mirror(spell::MirrorAxis::Vertical)),
+			(13, 10, 0)
+		);
+	}
+
+	/// A radio receiver for `range` messages saying "a", which moves the reality anchor toward `direction`.
+	fn receiver(range: &str, direction: &str) -> String {
+		format!(
+			r#"
+[symbols."r"]
+RadioReceiver = {{ {range} = "a" }}
+[symbols."P"]
+SelectRealityAnchor = []
+[symbols."E"]
+CardinalTargeter = "{direction}"
+[symbols."T"]
+Teleport = []
+
+# Layout
+r
+P
+E
+T
+"#
+		)
+	}
+
+	#[test]
+	fn targeted_radio_only_reaches_receivers_on_its_targets() {
+		let mut simulation = simulation(
+			r#"
+[symbols."^"]
+Keypress = "B"
+[symbols."P"]
+SelectRealityAnchor = []
+[symbols."E"]
+CardinalTargeter = "Right"
+[symbols."B"]
+RadioBroadcaster = { Targeted = "a" }
+
+# Layout
+^
+P
+E
+B
+"#,
+		);
+		simulation
+			.load_vault(1, 0, 0, &receiver("Targeted", "Down"))
+			.unwrap();
+		simulation
+			.load_vault(30, 10, 0, &receiver("Targeted", "Up"))
+			.unwrap();
+		simulation.press("B");
+		assert_eq!(anchor_position(&simulation), (0, 1, 0));
+	}
+
+	#[test]
+	fn contained_radio_only_reaches_receivers_in_its_own_axiom() {
+		// Each receiver ends a turn. The gate stops the receiver's own synapse,
+		// which has no casters, from running back into the broadcaster.
+		let receiver = r#"
+[symbols."r"]
+RadioReceiver = { Contained = "a" }
+[symbols."X"]
+TurnIncrementer = []
+
+# Layout
+r
+X
+"#;
+		let mut simulation = simulation(
+			r#"
+[symbols."^"]
+Keypress = "B"
+[symbols."P"]
+SelectRealityAnchor = []
+[symbols."B"]
+RadioBroadcaster = { Contained = "a" }
+[symbols."G"]
+IfCounterAtLeast = ["n", 0]
+[symbols."r"]
+RadioReceiver = { Contained = "a" }
+[symbols."X"]
+TurnIncrementer = []
+
+# Layout
+^
+P
+B
+G
+r
+X
+"#,
+		);
+		simulation.load_vault(30, 10, 0, receiver).unwrap();
+		simulation.press("B");
+		// Once through the receiver, and once more as the synapse itself runs into it.
+		assert_eq!(simulation.turns(), 2);
+	}
+
+	#[test]
+	fn local_radio_only_reaches_nearby_receivers() {
+		let mut simulation = simulation(
+			r#"
+[symbols."^"]
+Keypress = "B"
+[symbols."B"]
+RadioBroadcaster = { Local = "a" }
+
+# Layout
+^
+B
+"#,
+		);
+		simulation
+			.load_vault(13, 10, 0, &receiver("Local", "Down"))
+			.unwrap();
+		simulation
+			.load_vault(30, 10, 0, &receiver("Local", "Right"))
+			.unwrap();
+		simulation.press("B");
+		assert_eq!(anchor_position(&simulation), (0, 1, 0));
+	}
+
+	#[test]
+	fn deferred_teleports_to_one_tile_cancel_out() {
+		// The reality anchor and a wall both teleport to the tile between them.
+		let vault = r#"
+[symbols."^"]
+Keypress = "T"
+[symbols."P"]
+SelectRealityAnchor = []
+[symbols."W"]
+SelectSpecies = "Wall"
+[symbols."C"]
+SpecificCoord = [1, 0, 0]
+[symbols."T"]
+Teleport = []
+
+# Layout
+^
+P
+W
+C
+T
+"#;
+		let wall = "[symbols.\"#\"]\nWall = []\n\n# Layout\n#\n";
+		let wall_position = |simulation: &Simulation| position_of(simulation, Species::Wall);
+
+		// Immediately, the first one there takes the tile and the other runs into it.
+		let mut immediate = simulation(vault);
+		immediate.load_vault(2, 0, 0, wall).unwrap();
+		immediate.press("T");
+		assert_eq!(anchor_position(&immediate), (1, 0, 0));
+		assert_eq!(wall_position(&immediate), (2, 0, 0));
+
+		// Deferred, neither gets it.
+		let mut deferred = simulation(vault);
+		deferred.manager.effect_mode = spell::EffectMode::Deferred;
+		deferred.load_vault(2, 0, 0, wall).unwrap();
+		deferred.press("T");
+		assert_eq!(anchor_position(&deferred), (0, 0, 0));
+		assert_eq!(wall_position(&deferred), (2, 0, 0));
+	}
+
+	#[test]
+	fn pulling_skips_pieces_that_earlier_pulls_removed() {
+		// The reality anchor pulls two walls. The first one runs into it,
+		// and the collision annihilates the second before its turn comes.
+		let mut simulation = simulation(
+			r#"
+[symbols."^"]
+Keypress = "L"
+[symbols."P"]
+SelectRealityAnchor = []
+[symbols."A"]
+SpecificCoord = [2, 0, 0]
+[symbols."B"]
+SpecificCoord = [4, 0, 0]
+[symbols."L"]
+Pull = []
+[symbols."O"]
+OnCollision = "Terminal"
+[symbols."x"]
+Annihilate = []
+
+# Layout
+^ O
+P B
+A x
+B
+L
+"#,
+		);
+		simulation
+			.load_vault(2, 0, 0, "[symbols.\"#\"]\nWall = []\n\n# Layout\n# #\n")
+			.unwrap();
+		simulation.press("L");
+		assert_eq!(anchor_position(&simulation), (0, 0, 0));
+		let walls = simulation.manager.get_characters_of_species(Species::Wall);
+		assert_eq!(walls.len(), 1);
+		assert_eq!(walls[0].borrow().x, 1);
+	}
+
+	/// "G" moves the reality anchor one tile to the right, if the gate `gate` lets the synapse through.
+	/// The gate comes right after the anchor is selected, or right before if `before_selection` is set.
+	/// When spawned at (10, 10, 0), the gate stands on (10, 12, 0), or (10, 11, 0) if it comes first.
+	fn gated_move(gate: &str, before_selection: bool) -> Simulation {
+		let layout = if before_selection {
+			"^\nG\nP\nE\nT\n"
+		} else {
+			"^\nP\nG\nE\nT\n"
+		};
+		simulation(&format!(
+			r#"
+[symbols."^"]
+Keypress = "G"
+[symbols."G"]
+{gate}
+[symbols."P"]
+SelectRealityAnchor = []
+[symbols."E"]
+CardinalTargeter = "Right"
+[symbols."T"]
+Teleport = []
+
+# Layout
+{layout}"#
+		))
+	}
+
+	#[test]
+	fn if_species_at_checks_the_tile_at_its_offset() {
+		let gate = r#"IfSpeciesAt = [[2, 0], "Wall"]"#;
+		let mut passing = gated_move(gate, false);
+		passing
+			.load_vault(12, 12, 0, "[symbols.\"#\"]\nWall = []\n\n# Layout\n#\n")
+			.unwrap();
+		passing.press("G");
+		assert_eq!(anchor_position(&passing), (1, 0, 0));
+
+		let mut halting = gated_move(gate, false);
+		halting.press("G");
+		assert_eq!(anchor_position(&halting), (0, 0, 0));
+	}
+
+	#[test]
+	fn if_turn_modulo_checks_the_turn_count() {
+		let mut simulation = gated_move("IfTurnModulo = [2, 1]", false);
+		simulation.press("G");
+		assert_eq!(anchor_position(&simulation), (0, 0, 0));
+		simulation.step();
+		simulation.press("G");
+		assert_eq!(anchor_position(&simulation), (1, 0, 0));
+	}
+
+	#[test]
+	fn if_anchor_on_realm_checks_the_anchor_layer() {
+		let mut passing = gated_move("IfAnchorOnRealm = 0", false);
+		passing.press("G");
+		assert_eq!(anchor_position(&passing), (1, 0, 0));
+
+		let mut halting = gated_move("IfAnchorOnRealm = 1", false);
+		halting.press("G");
+		assert_eq!(anchor_position(&halting), (0, 0, 0));
+	}
+
+	#[test]
+	fn if_casters_empty_only_lets_casterless_synapses_through() {
+		let mut passing = gated_move("IfCastersEmpty = []", true);
+		passing.press("G");
+		assert_eq!(anchor_position(&passing), (1, 0, 0));
+
+		let mut halting = gated_move("IfCastersEmpty = []", false);
+		halting.press("G");
+		assert_eq!(anchor_position(&halting), (0, 0, 0));
+	}
+
+	#[test]
+	fn loading_from_on_load_is_stopped_as_an_overload() {
+		// Every nested load runs OnLoad one axiom deeper, until the overload depth is reached.
+		// That takes more stack than a test thread has by default, so give it what the main thread gets.
+		let test = std::thread::Builder::new()
+			.stack_size(8 << 20)
+			.spawn(|| {
+				let mut simulation = simulation(
+					r#"
+[symbols."^"]
+Keypress = "Right"
+[symbols."P"]
+SelectRealityAnchor = []
+[symbols."E"]
+CardinalTargeter = "Right"
+[symbols."T"]
+Teleport = []
+[symbols."S"]
+Keypress = "S"
+[symbols."%"]
+SaveGame = []
+[symbols."L"]
+Keypress = "L"
+[symbols."O"]
+OnLoad = []
+[symbols."&"]
+LoadGame = []
+
+# Layout
+^ S L O
+P % & &
+E
+T
+"#,
+				);
+				simulation.press("S");
+				simulation.press("Right");
+				simulation.press("L");
+				assert_eq!(anchor_position(&simulation), (0, 0, 0));
+				// The loaded world starts its next turn at the usual depth.
+				simulation.press("Right");
+				assert_eq!(anchor_position(&simulation), (1, 0, 0));
+			})
+			.unwrap();
+		test.join().unwrap();
+	}
+
+	#[test]
+	fn door_opens_on_the_third_press() {
+		let mut simulation = simulation(
+			r#"
+[symbols."^"]
+Keypress = "O"
+[symbols."P"]
+SelectRealityAnchor = []
+[symbols."+"]
+IncrementCounter = "presses"
+[symbols."="]
+IfCounterEquals = ["presses", 3]
+[symbols."E"]
+CardinalTargeter = "Right"
+[symbols."A"]
+Annihilate = []
+
+# Layout
+^
+P
++
+=
+E
+A
+"#,
+		);
+		simulation
+			.load_vault(1, 0, 0, "[symbols.\"#\"]\nWall = []\n\n# Layout\n#\n")
+			.unwrap();
+		for _ in 0..2 {
+			simulation.press("O");
+			assert!(simulation.manager.get_character_at(1, 0, 0).is_some());
+		}
+		simulation.press("O");
+		assert!(simulation.manager.get_character_at(1, 0, 0).is_none());
+		let anchor = simulation.manager.get_reality_anchor();
+		assert_eq!(anchor.borrow().counter("presses"), 3);
+	}
+
+	#[test]
+	fn counters_are_saved_and_loaded() {
+		let mut simulation = simulation(
+			r#"
+[symbols."^"]
+Keypress = "I"
+[symbols."P"]
+SelectRealityAnchor = []
+[symbols."+"]
+IncrementCounter = "presses"
+[symbols."S"]
+Keypress = "S"
+[symbols."%"]
+SaveGame = []
+[symbols."L"]
+Keypress = "L"
+[symbols."&"]
+LoadGame = []
+
+# Layout
+^ S L
+P % &
++
+"#,
+		);
+		let path =
+			std::env::temp_dir().join(format!("sdltgfp-counters-{}.toml", std::process::id()));
+		simulation.manager.save_slot = world::SaveSlot::File(path.clone());
+		let presses = |simulation: &Simulation| {
+			let anchor = simulation.manager.get_reality_anchor();
+			let presses = anchor.borrow().counter("presses");
+			presses
+		};
+		simulation.press("I");
+		simulation.press("I");
+		simulation.press("S");
+		simulation.press("I");
+		assert_eq!(presses(&simulation), 3);
+		simulation.press("L");
+		std::fs::remove_file(&path).unwrap();
+		assert_eq!(presses(&simulation), 2);
+	}
+}
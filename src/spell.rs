@@ -1,10 +1,18 @@
-use std::cell::RefCell;
-
 use crate::{
 	animation::TileEffect,
-	character::OrdDir,
-	world::{map_wrap, CharacterRef, Manager, SavePayload},
+	character::{EntityId, OrdDir},
+	fov::{self, line_between_two_points},
+	pathfind,
+	vault::Vault,
+	world::{map_wrap, wrapped_offset, CharacterRef, Manager, WORLD_COLS, WORLD_ROWS},
 };
+use std::collections::HashMap;
+
+/// How many waves a single call to `process_axioms` may propagate before its synapses are considered runaway.
+pub const OVERLOAD_THRESHOLD: usize = 500;
+/// How many calls to `process_axioms` may be nested inside each other
+/// (through contingencies and radios) before they are considered runaway.
+pub const OVERLOAD_DEPTH: usize = 64;
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Spell {
@@ -14,19 +22,108 @@ pub struct Spell {
 	pub description: String,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 struct CasterTarget {
-	caster: CharacterRef,
-	targets: Vec<(i32, i32, i32)>,
+	caster: EntityId,
+	targets: TargetSet,
 }
 
 impl CasterTarget {
-	pub fn new(caster: CharacterRef, targets: Vec<(i32, i32, i32)>) -> Self {
-		CasterTarget { caster, targets }
+	pub fn new(caster: &CharacterRef, targets: TargetSet) -> Self {
+		CasterTarget {
+			caster: caster.borrow().id,
+			targets,
+		}
 	}
 }
 
-#[derive(Clone, Debug)]
+/// The tiles targeted by a caster.
+///
+/// Each tile is only stored once.
+/// Filters are only applied when the targets are read with `resolve`,
+/// so they also cover tiles that were added after them.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+struct TargetSet {
+	tiles: Vec<(i32, i32, i32)>,
+	filters: Vec<TargetFilter>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+enum TargetFilter {
+	/// Remove the tile the caster stands on.
+	ExcludeSelf,
+	/// Remove tiles on which a piece of this species stands.
+	ExcludeSpecies(Species),
+	OnlyOccupied,
+	OnlyEmpty,
+}
+
+impl TargetSet {
+	fn new(tiles: Vec<(i32, i32, i32)>) -> Self {
+		let mut set = Self::default();
+		set.extend(tiles);
+		set
+	}
+
+	fn push(&mut self, tile: (i32, i32, i32)) {
+		if !self.tiles.contains(&tile) {
+			self.tiles.push(tile);
+		}
+	}
+
+	fn extend(&mut self, tiles: impl IntoIterator<Item = (i32, i32, i32)>) {
+		for tile in tiles {
+			self.push(tile);
+		}
+	}
+
+	/// Every tile, before any filtering.
+	fn tiles(&self) -> &[(i32, i32, i32)] {
+		&self.tiles
+	}
+
+	/// Move every tile, merging those that land on the same spot.
+	fn map(&mut self, f: impl FnMut((i32, i32, i32)) -> (i32, i32, i32)) {
+		let tiles = std::mem::take(&mut self.tiles);
+		self.extend(tiles.into_iter().map(f));
+	}
+
+	fn replace(&mut self, tiles: Vec<(i32, i32, i32)>) {
+		self.tiles.clear();
+		self.extend(tiles);
+	}
+
+	fn add_filter(&mut self, filter: TargetFilter) {
+		if !self.filters.contains(&filter) {
+			self.filters.push(filter);
+		}
+	}
+
+	/// The tiles that pass every filter, for the caster `caster`.
+	fn resolve(&self, manager: &Manager, caster: EntityId) -> Vec<(i32, i32, i32)> {
+		let mut tiles = self.tiles.clone();
+		for filter in &self.filters {
+			match filter {
+				TargetFilter::ExcludeSelf => {
+					if let Some(caster) = manager.get_character(caster) {
+						let caster = caster.borrow();
+						tiles.retain(|tile| *tile != (caster.x, caster.y, caster.z));
+					}
+				}
+				TargetFilter::ExcludeSpecies(species) => tiles.retain(|(x, y, z)| {
+					manager
+						.get_character_at(*x, *y, *z)
+						.is_none_or(|piece| piece.borrow().species != *species)
+				}),
+				TargetFilter::OnlyOccupied => tiles = filter_targets_by_occupied(manager, &tiles),
+				TargetFilter::OnlyEmpty => tiles = filter_targets_by_unoccupied(manager, &tiles),
+			}
+		}
+		tiles
+	}
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Synapse {
 	casters: Vec<CasterTarget>,
 	momentum: OrdDir,
@@ -43,12 +140,9 @@ impl Synapse {
 			visited: Vec::new(),
 		}
 	}
-	pub fn new_with_caster(x: i32, y: i32, z: i32, caster: CharacterRef) -> Self {
+	pub fn new_with_caster(x: i32, y: i32, z: i32, caster: &CharacterRef) -> Self {
 		Synapse {
-			casters: vec![CasterTarget {
-				caster,
-				targets: Vec::new(),
-			}],
+			casters: vec![CasterTarget::new(caster, TargetSet::default())],
 			momentum: OrdDir::Up,
 			pulse: (x, y, z),
 			visited: Vec::new(),
@@ -57,10 +151,7 @@ impl Synapse {
 	pub fn new_with_casters(x: i32, y: i32, z: i32, caster_refs: &[CharacterRef]) -> Self {
 		let mut casters: Vec<CasterTarget> = Vec::new();
 		for caster in caster_refs {
-			casters.push(CasterTarget {
-				caster: caster.clone(),
-				targets: Vec::new(),
-			});
+			casters.push(CasterTarget::new(caster, TargetSet::default()));
 		}
 		Synapse {
 			casters,
@@ -89,15 +180,119 @@ pub fn match_axiom_with_codename(axiom: &Species) -> Option<&str> {
 	}
 }
 
+/// How far a radio message travels.
+///
+/// Both the broadcaster's and the receiver's range must allow a message for it to be received.
+/// Regardless of range, messages only reach receivers on the reality anchor's layer or above.
 #[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
 pub enum Range {
+	/// Only receivers standing on one of the broadcasting synapse's targets.
 	Targeted(String),
+	/// Only receivers that are part of the same contiguous axiom as the broadcaster.
 	Contained(String),
+	/// Only receivers on the same layer as the broadcaster, within `LOCAL_RADIO_RADIUS`.
 	Local(String),
+	/// Every receiver. The receiver starts a new synapse.
 	Global(String),
+	/// Every receiver. The broadcasting synapse continues from the receiver,
+	/// keeping its casters and targets.
 	Synaptic(String),
 }
 
+/// How far a `Range::Local` message travels, in tiles.
+pub const LOCAL_RADIO_RADIUS: i32 = 7;
+/// How far a `WatchBot` can see, in tiles.
+pub const WATCHBOT_SIGHT: usize = 6;
+
+impl Range {
+	pub fn message(&self) -> &str {
+		match self {
+			Range::Targeted(message)
+			| Range::Contained(message)
+			| Range::Local(message)
+			| Range::Global(message)
+			| Range::Synaptic(message) => message,
+		}
+	}
+
+	/// Whether a message sent from `broadcaster` to `receiver` is allowed by this range.
+	///
+	/// `targets` are the broadcasting synapse's targets,
+	/// and `circuit` is the contiguous axiom containing the broadcaster.
+	fn reaches(
+		&self,
+		broadcaster: (i32, i32, i32),
+		receiver: (i32, i32, i32),
+		targets: &[(i32, i32, i32)],
+		circuit: &[(i32, i32, i32)],
+	) -> bool {
+		match self {
+			Range::Targeted(_) => targets.contains(&receiver),
+			Range::Contained(_) => circuit.contains(&receiver),
+			Range::Local(_) => {
+				let (dx, dy) = wrapped_offset(broadcaster, receiver);
+				broadcaster.2 == receiver.2
+					&& dx * dx + dy * dy <= LOCAL_RADIO_RADIUS * LOCAL_RADIO_RADIUS
+			}
+			Range::Global(_) | Range::Synaptic(_) => true,
+		}
+	}
+}
+
+/// The line or point across which a `Mirror` reflects the reality anchor's moves.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum MirrorAxis {
+	/// Up and down are swapped.
+	Horizontal,
+	/// Left and right are swapped.
+	Vertical,
+	/// Every move is reversed.
+	Point,
+}
+
+impl MirrorAxis {
+	pub fn reflect(self, dir: OrdDir) -> OrdDir {
+		let (x, y) = dir.as_offset();
+		let offset = match self {
+			MirrorAxis::Horizontal => (x, -y),
+			MirrorAxis::Vertical => (-x, y),
+			MirrorAxis::Point => (-x, -y),
+		};
+		OrdDir::from_offset(offset).expect("Reflecting a direction can't cancel it out")
+	}
+}
+
+impl Species {
+	/// Whether this species is an axiom, as opposed to a creature or a wall.
+	pub fn is_axiom(&self) -> bool {
+		!matches!(
+			self,
+			Species::Wall
+				| Species::Terminal
+				| Species::WorldStem
+				| Species::EpsilonHead
+				| Species::EpsilonTail(_)
+				| Species::WatchBot
+				| Species::PushCrate
+				| Species::Mirror(_)
+		)
+	}
+
+	/// Where pieces of this species fall in a segmented body: 0 for a head, then 1, 2... down the tail.
+	pub fn segment_index(&self) -> Option<usize> {
+		match self {
+			Species::EpsilonHead => Some(0),
+			Species::EpsilonTail(index) => Some(*index),
+			_ => None,
+		}
+	}
+
+	/// Whether pieces of this species get pushed one tile further when something moves into them.
+	pub fn is_pushable(&self) -> bool {
+		matches!(self, Species::PushCrate)
+	}
+}
+
 #[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
 pub enum Species {
 	Wall,
@@ -107,13 +302,32 @@ pub enum Species {
 	EpsilonTail(usize),
 	WatchBot,
 	PushCrate,
+	Mirror(MirrorAxis),
 	// AXIOMS
 
 	// Contingencies
 	Keypress(String),
 	RadioReceiver(Range),
 	OnTurn,
+	OnTurnEvery(usize),
 	OnCollision(Box<Species>),
+	OnPush(Box<Species>),
+	OnSelfCollision,
+	OnAnchorSpotted,
+	OnOverload,
+	/// Fires when a piece arrives on the tile this far away from the contingency.
+	OnEnterTile((i32, i32, i32)),
+	OnAnchorSwap,
+	OnTransform,
+	OnLoad,
+
+	// Gates
+	IfSpeciesAt((i32, i32), Box<Species>),
+	IfTurnModulo(usize, usize),
+	IfAnchorOnRealm(i32),
+	IfCastersEmpty,
+	IfCounterEquals(String, i32),
+	IfCounterAtLeast(String, i32),
 
 	// Anointers
 	SelectSpecies(Box<Species>),
@@ -128,6 +342,8 @@ pub enum Species {
 	SelfTargeter,
 	MomentumTouch,
 	MomentumBeam,
+	VisionTargeter(usize),
+	ConeTargeter(OrdDir, usize),
 	SpecificCoord((i32, i32, i32)),
 
 	// Mutators
@@ -136,28 +352,430 @@ pub enum Species {
 	Orbit(usize),
 	Halo(usize),
 	BeamToTargets,
+	ExcludeSelf,
+	ExcludeSpecies(Box<Species>),
+	OnlyOccupied,
+	OnlyEmpty,
 
 	// Functions
 	Teleport,
 	Twinning,
+	CopyPaste(usize),
 	SwapAnchor,
 	RadioBroadcaster(Range),
 	Fireworks,
 	SaveGame,
 	LoadGame,
+	Rewind(usize),
 	TurnIncrementer,
+	Pull,
+	Dash,
+	IncrementCounter(String),
+	ResetCounter(String),
+	Delay(usize),
+	Summon(Box<Species>),
+	Annihilate,
 }
 
 pub struct Result {
 	pub new_manager: Option<crate::world::Manager>,
 }
 
+/// A synapse suspended by `Delay`, waiting for its turn to come.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct DelayedSynapse {
+	/// The synapse resumes when the turn counter reaches this value.
+	pub turn: usize,
+	pub synapse: Synapse,
+}
+
+/// The tiles of a caster and of the target it would have swapped the reality anchor with.
+pub type PlannedSwap = ((i32, i32, i32), (i32, i32, i32));
+
+/// Everything a keypress would have done, collected by `preview_keypress`.
+#[derive(Clone, Debug, Default)]
+pub struct Preview {
+	/// Every tile that was targeted at some point during propagation.
+	pub targets: Vec<(i32, i32, i32)>,
+	/// Pieces that would have moved, through `Teleport`, `Pull` or `Dash`.
+	pub teleports: Vec<PlannedTeleport>,
+	/// Tiles whose occupant would have been transformed, and the species they would have become.
+	pub twins: Vec<((i32, i32, i32), Species)>,
+	/// Empty tiles that would have been filled by `CopyPaste` or `Summon`, and the species they would have received.
+	pub pastes: Vec<((i32, i32, i32), Species)>,
+	/// Tiles whose occupant would have been annihilated. The reality anchor resists, so it is never listed.
+	pub annihilations: Vec<(i32, i32, i32)>,
+	/// Pairs of tiles whose occupants would have swapped the reality anchor.
+	pub swaps: Vec<PlannedSwap>,
+	/// Whether the world state would have been saved.
+	pub save: bool,
+}
+
+/// The position of a caster, along with the tiles it targets.
+pub type TracedCaster = ((i32, i32, i32), Vec<(i32, i32, i32)>);
+
+/// A single step taken by a synapse, recorded by `process_axioms` while tracing is enabled.
+#[derive(Clone, Debug)]
+pub struct TraceStep {
+	/// How many calls to `process_axioms` were nested when this step was taken.
+	pub depth: usize,
+	pub pulse: (i32, i32, i32),
+	/// The axiom that was found under the pulse.
+	pub axiom: Species,
+	/// The position of each caster after the axiom took effect, along with its targets.
+	pub casters: Vec<TracedCaster>,
+	/// Where this synapse will pulse next, if it survives.
+	pub next: Option<(i32, i32, i32)>,
+	/// Where the new synapses branching off from this one will pulse next.
+	pub branches: Vec<(i32, i32, i32)>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PlannedTeleport {
+	pub species: Species,
+	pub from: (i32, i32, i32),
+	pub to: (i32, i32, i32),
+}
+
+/// When Functions change the world.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum EffectMode {
+	/// Functions take effect as soon as a synapse reaches them,
+	/// so later synapses in the same wave see the changed world.
+	#[default]
+	Immediate,
+	/// Functions queue their effects, which are resolved together once every synapse
+	/// has taken its step (see `resolve_effects`).
+	Deferred,
+}
+
+/// A change to the world requested by a Function axiom.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Effect {
+	Teleport {
+		piece: EntityId,
+		to: (i32, i32, i32),
+	},
+	Twin {
+		victim: EntityId,
+		species: Species,
+	},
+	/// Move the reality anchor from `from` to `to`, if it is still on `from`.
+	SwapAnchor {
+		from: EntityId,
+		to: EntityId,
+	},
+	/// Spawn `vault` with its top-left corner on `origin`, on empty tiles only.
+	Paste {
+		origin: (i32, i32, i32),
+		vault: Vault,
+	},
+	/// Move `piece` toward `toward`, one orthogonal step at a time, until it is blocked.
+	Pull {
+		piece: EntityId,
+		toward: EntityId,
+	},
+	/// Move `piece` in an orthogonal line away from `away_from` until it is blocked.
+	Dash {
+		piece: EntityId,
+		away_from: (i32, i32, i32),
+	},
+	/// Remove `piece` from the world, unless it is the reality anchor.
+	Annihilate {
+		piece: EntityId,
+	},
+}
+
+/// How many steps a slide may take before it is stopped, even if nothing blocked it.
+const MAX_SLIDE: usize = if WORLD_COLS > WORLD_ROWS {
+	WORLD_COLS
+} else {
+	WORLD_ROWS
+};
+
+impl Effect {
+	fn apply(&self, manager: &Manager) {
+		match self {
+			Effect::Teleport { piece, to } => {
+				if let Some(piece) = manager.get_character(*piece) {
+					// This will return an intentional error if a collision happens.
+					// This collision could be used for a cool Contingency, like starting
+					// dialogue.
+					let _ = manager.teleport_piece(&piece, to.0, to.1, to.2);
+				}
+			}
+			Effect::Twin { victim, species } => {
+				if let Some(victim) = manager.get_character(*victim) {
+					let (x, y, z) = {
+						let victim = victim.borrow();
+						(victim.x, victim.y, victim.z)
+					};
+					// The occupancy index keeps track of which species watch tiles.
+					manager.occupancy.borrow_mut().remove(&victim, x, y, z);
+					let previous =
+						std::mem::replace(&mut victim.borrow_mut().species, species.clone());
+					manager.occupancy.borrow_mut().insert(&victim);
+					if previous != *species {
+						manager.unlink_segments(&victim);
+						manager.link_segments(&victim);
+						trigger_contingency(
+							manager,
+							&Species::OnTransform,
+							Some(ContingencyPacket::Transform { piece: victim }),
+						);
+					}
+				}
+			}
+			Effect::SwapAnchor { from, to } => {
+				if manager.reality_anchor.get() != *from {
+					return;
+				}
+				let (Some(previous), Some(current)) =
+					(manager.get_character(*from), manager.get_character(*to))
+				else {
+					return;
+				};
+				manager.reality_anchor.set(*to);
+				trigger_contingency(
+					manager,
+					&Species::OnAnchorSwap,
+					Some(ContingencyPacket::AnchorSwap { previous, current }),
+				);
+			}
+			Effect::Paste { origin, vault } => {
+				manager.paste_vault(origin.0, origin.1, origin.2, vault);
+			}
+			Effect::Annihilate { piece } => {
+				if *piece == manager.reality_anchor.get() {
+					manager
+						.console
+						.borrow_mut()
+						.print_system("The reality anchor resisted annihilation.");
+				} else {
+					manager.despawn(*piece);
+				}
+			}
+			// Moving into a piece fires OnCollision, like any other move.
+			Effect::Pull { piece, toward } => {
+				let (Some(piece), Some(toward)) = (
+					manager.get_character(*piece),
+					manager.get_character(*toward),
+				) else {
+					return;
+				};
+				for _ in 0..MAX_SLIDE {
+					let offset = {
+						let (piece, toward) = (piece.borrow(), toward.borrow());
+						wrapped_offset((piece.x, piece.y, piece.z), (toward.x, toward.y, toward.z))
+					};
+					let Some(dir) = OrdDir::orthogonal_from_offset(offset) else {
+						break;
+					};
+					if manager.move_piece(&piece, dir).is_err() {
+						break;
+					}
+				}
+			}
+			Effect::Dash { piece, away_from } => {
+				let Some(piece) = manager.get_character(*piece) else {
+					return;
+				};
+				let (dx, dy) = {
+					let piece = piece.borrow();
+					wrapped_offset((piece.x, piece.y, piece.z), *away_from)
+				};
+				let Some(dir) = OrdDir::orthogonal_from_offset((-dx, -dy)) else {
+					return;
+				};
+				for _ in 0..MAX_SLIDE {
+					if manager.move_piece(&piece, dir).is_err() {
+						break;
+					}
+				}
+			}
+		}
+	}
+}
+
+/// Where a piece sliding from `from` would stop, if only the pieces already in the world blocked it.
+///
+/// `step` gives the direction of the next step from a tile, or `None` to stop there.
+/// This is what `Effect::Pull` and `Effect::Dash` do, without moving anything, for previews.
+fn slide_destination(
+	manager: &Manager,
+	from: (i32, i32, i32),
+	mut step: impl FnMut((i32, i32, i32)) -> Option<OrdDir>,
+) -> (i32, i32, i32) {
+	let mut position = from;
+	for _ in 0..MAX_SLIDE {
+		let Some(dir) = step(position) else {
+			break;
+		};
+		let (dx, dy) = dir.as_offset();
+		let next = map_wrap(position.0 + dx, position.1 + dy, position.2);
+		if next != from && manager.get_character_at(next.0, next.1, next.2).is_some() {
+			break;
+		}
+		position = next;
+	}
+	position
+}
+
+/// Apply `effect` right away, or queue it if the manager is in `EffectMode::Deferred`.
+fn apply_or_defer(manager: &Manager, effect: Effect) {
+	match manager.effect_mode {
+		EffectMode::Immediate => effect.apply(manager),
+		EffectMode::Deferred => manager.effect_queue.borrow_mut().push(effect),
+	}
+}
+
+/// Apply every queued effect at once.
+///
+/// Conflicting effects cancel each other out instead of depending on synapse order:
+/// - A piece transformed into two different species stays as it is.
+/// - A piece ordered to teleport to two different tiles stays where it is.
+/// - Pieces teleporting to the same tile all stay where they are.
+/// - A piece both pulled and dashing, or pulled in two directions, stays where it is.
+/// - Two different anchor swaps both fail.
+/// - A tile that two pastes or summons would fill with different species stays empty.
+///
+/// Twins are applied first, then teleports and slides in order of entity ID,
+/// then the anchor swap, then annihilations, then pastes and summons.
+pub fn resolve_effects(manager: &Manager) {
+	let effects = std::mem::take(&mut *manager.effect_queue.borrow_mut());
+	let mut twins = Vec::new();
+	let mut teleports = Vec::new();
+	let mut swaps = Vec::new();
+	let mut pastes = Vec::new();
+	let mut slides = Vec::new();
+	let mut annihilations = Vec::new();
+	for effect in effects {
+		match effect {
+			Effect::Twin { victim, species } => twins.push((victim, species)),
+			Effect::Teleport { piece, to } => teleports.push((piece, map_wrap(to.0, to.1, to.2))),
+			Effect::SwapAnchor { from, to } => swaps.push((from, to)),
+			Effect::Paste { origin, vault } => pastes.push((origin, vault)),
+			Effect::Pull { piece, .. } | Effect::Dash { piece, .. } => slides.push((piece, effect)),
+			Effect::Annihilate { piece } => annihilations.push(piece),
+		}
+	}
+	// The same effect requested twice is not a conflict.
+	twins.sort_by_key(|(victim, _)| *victim);
+	twins.dedup();
+	teleports.sort();
+	teleports.dedup();
+	swaps.sort();
+	swaps.dedup();
+	slides.sort_by_key(|(piece, _)| *piece);
+	slides.dedup();
+
+	for (victim, species) in &twins {
+		if twins.iter().filter(|(other, _)| other == victim).count() > 1 {
+			manager.console.borrow_mut().print_system(format!(
+				"Twinning into {species:?} was cancelled by another Twinning."
+			));
+			continue;
+		}
+		Effect::Twin {
+			victim: *victim,
+			species: species.clone(),
+		}
+		.apply(manager);
+	}
+	for (piece, to) in &teleports {
+		if teleports.iter().filter(|(other, _)| other == piece).count() > 1 {
+			manager
+				.console
+				.borrow_mut()
+				.print_system("A piece was ordered to teleport to several tiles, and stayed put.");
+			continue;
+		}
+		if teleports.iter().filter(|(_, other)| other == to).count() > 1 {
+			manager.console.borrow_mut().print_system(format!(
+				"Several pieces tried to teleport to {to:?}, and all stayed put."
+			));
+			continue;
+		}
+		Effect::Teleport {
+			piece: *piece,
+			to: *to,
+		}
+		.apply(manager);
+	}
+	for (piece, slide) in &slides {
+		if slides.iter().filter(|(other, _)| other == piece).count() > 1 {
+			manager
+				.console
+				.borrow_mut()
+				.print_system("A piece was pulled several ways at once, and stayed put.");
+			continue;
+		}
+		slide.apply(manager);
+	}
+	match swaps.as_slice() {
+		[] => (),
+		[(from, to)] => Effect::SwapAnchor {
+			from: *from,
+			to: *to,
+		}
+		.apply(manager),
+		_ => manager
+			.console
+			.borrow_mut()
+			.print_system("Several anchor swaps happened at once, and all failed."),
+	}
+	annihilations.sort();
+	annihilations.dedup();
+	for piece in annihilations {
+		Effect::Annihilate { piece }.apply(manager);
+	}
+	let mut claims = Vec::new();
+	for ((x, y, z), vault) in &pastes {
+		for (xoff, yoff, species) in &vault.characters {
+			claims.push((map_wrap(x + xoff, y + yoff, *z), species.clone()));
+		}
+	}
+	for (origin, mut vault) in pastes {
+		vault.characters.retain(|(xoff, yoff, species)| {
+			let tile = map_wrap(origin.0 + xoff, origin.1 + yoff, origin.2);
+			let contested = claims
+				.iter()
+				.any(|(other_tile, other)| *other_tile == tile && other != species);
+			if contested {
+				manager.console.borrow_mut().print_system(format!(
+					"Several pastes tried to fill {tile:?}, and it stayed empty."
+				));
+			}
+			!contested
+		});
+		Effect::Paste { origin, vault }.apply(manager);
+	}
+}
+
 #[derive(Clone, Debug)]
 pub enum ContingencyPacket {
 	Collision {
 		collided: CharacterRef,
 		collider: CharacterRef,
 	},
+	Push {
+		pushed: CharacterRef,
+		pusher: CharacterRef,
+	},
+	Spotted {
+		watcher: CharacterRef,
+		anchor: CharacterRef,
+	},
+	Overload {
+		axioms: Vec<CharacterRef>,
+	},
+	AnchorSwap {
+		previous: CharacterRef,
+		current: CharacterRef,
+	},
+	Transform {
+		piece: CharacterRef,
+	},
 }
 
 pub fn trigger_contingency(
@@ -167,22 +785,24 @@ pub fn trigger_contingency(
 	responsible: Option<ContingencyPacket>,
 ) -> Result {
 	let mut new_manager = None;
-	for axiom in &world_manager.characters {
+	let characters = world_manager.characters.borrow().clone();
+	for axiom in &characters {
 		let axiom = axiom.borrow();
 		let (x, y, z, species) = (axiom.x, axiom.y, axiom.z, &axiom.species);
 		// The axiom needs to be "above" or "equal" to the player's Z level to trigger.
-		let current_z = world_manager.reality_anchor.borrow().z;
+		let current_z = world_manager.get_reality_anchor().borrow().z;
 		if species == contingency && z <= current_z {
 			drop(axiom);
 			match contingency {
-				Species::OnTurn => {
+				Species::OnTurn | Species::OnTurnEvery(_) => {
 					new_manager =
 						process_axioms(vec![Synapse::new(x, y, z)], world_manager).new_manager;
 				}
 				// Both an anointer and a contingency, it makes both the collider and collided become casters.
-				Species::OnCollision(_) => {
+				// OnSelfCollision only fires when a piece runs into its own body.
+				Species::OnCollision(_) | Species::OnSelfCollision => {
 					let responsible = responsible.clone().expect(
-						"It should be impossible for OnCollision to be triggered without a responsible entity"
+						"It should be impossible for a collision to be triggered without a responsible entity"
 					);
 					if let ContingencyPacket::Collision { collided, collider } = responsible {
 						new_manager = process_axioms(
@@ -191,9 +811,80 @@ pub fn trigger_contingency(
 						)
 						.new_manager;
 					} else {
-						panic!("The packet sent to OnCollision should always be a collision.");
+						panic!("The packet sent to {contingency:?} should always be a collision.");
+					}
+				}
+				// Both an anointer and a contingency, it makes both the pushed piece and its pusher become casters.
+				Species::OnPush(_) => {
+					if let Some(ContingencyPacket::Push { pushed, pusher }) = responsible.clone() {
+						new_manager = process_axioms(
+							vec![Synapse::new_with_casters(x, y, z, &[pushed, pusher])],
+							world_manager,
+						)
+						.new_manager;
+					} else {
+						panic!("The packet sent to OnPush should always be a push.");
 					}
 				}
+				// Both an anointer and a contingency, it makes both the watcher and the reality anchor become casters.
+				Species::OnAnchorSpotted => {
+					if let Some(ContingencyPacket::Spotted { watcher, anchor }) =
+						responsible.clone()
+					{
+						new_manager = process_axioms(
+							vec![Synapse::new_with_casters(x, y, z, &[anchor, watcher])],
+							world_manager,
+						)
+						.new_manager;
+					} else {
+						panic!("The packet sent to OnAnchorSpotted should always be a sighting.");
+					}
+				}
+				// Both an anointer and a contingency, it makes all the overloaded axioms become casters.
+				Species::OnOverload => {
+					let responsible = responsible.clone().expect(
+						"It should be impossible for OnOverload to be triggered without overloaded axioms"
+					);
+					if let ContingencyPacket::Overload { axioms } = responsible {
+						new_manager = process_axioms(
+							vec![Synapse::new_with_casters(x, y, z, &axioms)],
+							world_manager,
+						)
+						.new_manager;
+					} else {
+						panic!("The packet sent to OnOverload should always be an overload.");
+					}
+				}
+				// Both an anointer and a contingency, it makes the new and the previous anchor become casters.
+				Species::OnAnchorSwap => {
+					if let Some(ContingencyPacket::AnchorSwap { previous, current }) =
+						responsible.clone()
+					{
+						new_manager = process_axioms(
+							vec![Synapse::new_with_casters(x, y, z, &[current, previous])],
+							world_manager,
+						)
+						.new_manager;
+					} else {
+						panic!("The packet sent to OnAnchorSwap should always be an anchor swap.");
+					}
+				}
+				// Both an anointer and a contingency, it makes the transformed piece become a caster.
+				Species::OnTransform => {
+					if let Some(ContingencyPacket::Transform { piece }) = responsible.clone() {
+						new_manager = process_axioms(
+							vec![Synapse::new_with_caster(x, y, z, &piece)],
+							world_manager,
+						)
+						.new_manager;
+					} else {
+						panic!("The packet sent to OnTransform should always be a transformation.");
+					}
+				}
+				Species::OnLoad => {
+					new_manager =
+						process_axioms(vec![Synapse::new(x, y, z)], world_manager).new_manager;
+				}
 				_ => (),
 			}
 		}
@@ -201,13 +892,182 @@ pub fn trigger_contingency(
 	Result { new_manager }
 }
 
+/// Fire every `OnEnterTile` watching `tile`, which `piece` just arrived on.
+///
+/// Both an anointer and a contingency, it makes the piece that arrived on the tile become a caster.
+/// The watchers are looked up in the occupancy index, rather than among all characters.
+pub fn trigger_tile_entry(
+	world_manager: &Manager,
+	tile: (i32, i32, i32),
+	piece: &CharacterRef,
+) -> Result {
+	let mut new_manager = None;
+	let watchers = world_manager
+		.occupancy
+		.borrow()
+		.watchers(tile.0, tile.1, tile.2)
+		.to_vec();
+	// The axiom needs to be "above" or "equal" to the player's Z level to trigger.
+	let current_z = world_manager.get_reality_anchor().borrow().z;
+	for watcher in watchers {
+		let (x, y, z) = {
+			let watcher = watcher.borrow();
+			(watcher.x, watcher.y, watcher.z)
+		};
+		if z <= current_z {
+			new_manager = process_axioms(
+				vec![Synapse::new_with_caster(x, y, z, piece)],
+				world_manager,
+			)
+			.new_manager;
+		}
+	}
+	Result { new_manager }
+}
+
+/// Fire every `Keypress` axiom bound to the key called `key`.
+///
+/// `key` uses the same names as `Species::Keypress`, which are SDL key names ("Up", "R"...).
+///
+/// If tracing is enabled, the trace is cleared so that it only contains this keypress.
+pub fn trigger_keypress(world_manager: &Manager, key: &str) -> Result {
+	world_manager.record_history();
+	if let Some(trace) = world_manager.trace.borrow_mut().as_mut() {
+		trace.clear();
+	}
+	let mut new_manager = None;
+	let characters = world_manager.characters.borrow().clone();
+	for axiom in &characters {
+		let axiom = axiom.borrow();
+		let (x, y, z, species) = (axiom.x, axiom.y, axiom.z, &axiom.species);
+		if let Species::Keypress(bound_key) = species {
+			// SDL key names are case-insensitive.
+			if bound_key.eq_ignore_ascii_case(key) {
+				drop(axiom);
+				new_manager =
+					process_axioms(vec![Synapse::new(x, y, z)], world_manager).new_manager;
+			}
+		}
+	}
+	Result { new_manager }
+}
+
+/// Run the axioms bound to `key` on a throwaway copy of the world,
+/// collecting what every Function would have done without applying any of it.
+pub fn preview_keypress(world_manager: &Manager, key: &str) -> Preview {
+	let mut sandbox = world_manager.clone();
+	sandbox.restore(&world_manager.save_payload());
+	*sandbox.preview.borrow_mut() = Some(Preview::default());
+	trigger_keypress(&sandbox, key);
+	sandbox.preview.take().unwrap_or_default()
+}
+
+/// Advance the turn counter, then fire `OnTurn`, the `OnTurnEvery` whose period has come,
+/// and the delayed synapses that are due, in that order.
+pub fn increment_turn(manager: &Manager) -> Result {
+	let mut turn_counter = manager.turn_count.borrow_mut();
+	turn_counter.turns += 1;
+	let turns = turn_counter.turns;
+	drop(turn_counter);
+	let mut new_manager = trigger_contingency(manager, &Species::OnTurn, None).new_manager;
+
+	let mut periods = Vec::new();
+	for piece in manager.characters.borrow().iter() {
+		if let Species::OnTurnEvery(period) = piece.borrow().species {
+			if period != 0 && turns.is_multiple_of(period) && !periods.contains(&period) {
+				periods.push(period);
+			}
+		}
+	}
+	for period in periods {
+		let result = trigger_contingency(manager, &Species::OnTurnEvery(period), None);
+		if result.new_manager.is_some() {
+			new_manager = result.new_manager;
+		}
+	}
+
+	let (due, pending): (Vec<DelayedSynapse>, Vec<DelayedSynapse>) =
+		std::mem::take(&mut *manager.delayed.borrow_mut())
+			.into_iter()
+			.partition(|delayed| delayed.turn <= turns);
+	*manager.delayed.borrow_mut() = pending;
+	if !due.is_empty() {
+		let synapses = due.into_iter().map(|delayed| delayed.synapse).collect();
+		let result = process_axioms(synapses, manager);
+		if result.new_manager.is_some() {
+			new_manager = result.new_manager;
+		}
+	}
+
+	manager.step_patrols();
+	let result = watch_for_anchor(manager);
+	if result.new_manager.is_some() {
+		new_manager = result.new_manager;
+	}
+	Result { new_manager }
+}
+
+/// Fire `OnAnchorSpotted` once for every `WatchBot` that can see the reality anchor.
+fn watch_for_anchor(manager: &Manager) -> Result {
+	let mut new_manager = None;
+	let characters = manager.characters.borrow().clone();
+	for watcher in &characters {
+		let (id, position) = {
+			let watcher = watcher.borrow();
+			if watcher.species != Species::WatchBot {
+				continue;
+			}
+			(watcher.id, (watcher.x, watcher.y, watcher.z))
+		};
+		// Earlier sightings may have removed this watcher.
+		if manager.get_character(id).is_none() {
+			continue;
+		}
+		let anchor = manager.get_reality_anchor();
+		let anchor_position = {
+			let anchor = anchor.borrow();
+			(anchor.x, anchor.y, anchor.z)
+		};
+		// Only anchors within sight range are worth tracing a line to.
+		let (dx, dy) = wrapped_offset(position, anchor_position);
+		let sight = WATCHBOT_SIGHT as i32;
+		if position.2 == anchor_position.2
+			&& (dx, dy) != (0, 0)
+			&& dx * dx + dy * dy <= sight * sight
+			&& fov::line_of_sight(manager, position, anchor_position)
+		{
+			let result = trigger_contingency(
+				manager,
+				&Species::OnAnchorSpotted,
+				Some(ContingencyPacket::Spotted {
+					watcher: watcher.clone(),
+					anchor,
+				}),
+			);
+			if result.new_manager.is_some() {
+				new_manager = result.new_manager;
+			}
+		}
+	}
+	Result { new_manager }
+}
+
 pub fn process_axioms(mut synapses: Vec<Synapse>, manager: &Manager) -> Result {
 	let mut new_manager = None;
+	let depth = manager.axiom_depth.get() + 1;
+	manager.axiom_depth.set(depth);
+	if depth > OVERLOAD_DEPTH {
+		// Axioms keep triggering more axioms. Stop here, and let the outermost call report it.
+		record_overload(manager, &synapses);
+		synapses.clear();
+	}
 	let mut loop_danger_count = 0;
-	while !synapses.is_empty() {
+	// Effects queued by nested calls are resolved by the outermost one.
+	while !synapses.is_empty() || (depth == 1 && !manager.effect_queue.borrow().is_empty()) {
 		loop_danger_count += 1;
-		if loop_danger_count > 500 {
-			panic!("Infinite loop in axioms (use this for something cool later!)");
+		if loop_danger_count > OVERLOAD_THRESHOLD {
+			record_overload(manager, &synapses);
+			break;
 		}
 		let mut syn_count = 0;
 		// Create a temporary vector to hold new synapses
@@ -220,16 +1080,63 @@ pub fn process_axioms(mut synapses: Vec<Synapse>, manager: &Manager) -> Result {
 			let curr_axiom = match manager.get_character_at(pulse_x, pulse_y, pulse_z) {
 				Some(axiom) => axiom,
 				None => {
-					// Would this happen if a location was spread to, then made unavailable?
-					// FIXME This is very suspicious.
+					// The axiom was annihilated or moved away before the synapse could reach it.
 					synapses_to_remove.push(syn_count);
 					syn_count += 1;
 					continue;
 				}
 			};
 			let curr_ax_species = curr_axiom.borrow().species.clone();
+			// Set by gates whose condition failed, which stops the synapse here.
+			let mut halted = false;
+			// Set by `Delay`, which suspends the synapse after it takes this step.
+			let mut delay = None;
 			match &curr_ax_species {
 				Species::Keypress(_) => (),
+				// Only continue if a piece of `species` stands `offset` tiles away from this gate.
+				Species::IfSpeciesAt((xoff, yoff), species) => {
+					let (x, y, z) = map_wrap(pulse_x + xoff, pulse_y + yoff, pulse_z);
+					halted = manager
+						.get_character_at(x, y, z)
+						.is_none_or(|piece| piece.borrow().species != **species);
+				}
+				// Only continue on turns where the turn count divided by `n` leaves `k`.
+				Species::IfTurnModulo(n, k) => {
+					let turns = manager.turn_count.borrow().turns;
+					halted = *n == 0 || turns % n != *k;
+				}
+				// Only continue if the reality anchor is on layer `realm`.
+				Species::IfAnchorOnRealm(realm) => {
+					halted = manager.get_reality_anchor().borrow().z != *realm;
+				}
+				// Only continue if nothing has been anointed yet.
+				Species::IfCastersEmpty => {
+					halted = !synapse.casters.is_empty();
+				}
+				// Only keep the Casters whose counter `name` is `value`,
+				// and stop if none are left.
+				Species::IfCounterEquals(name, value) => {
+					synapse
+						.casters
+						.retain(|CasterTarget { caster, targets: _ }| {
+							manager
+								.get_character(*caster)
+								.is_some_and(|caster| caster.borrow().counter(name) == *value)
+						});
+					halted = synapse.casters.is_empty();
+				}
+				// Only keep the Casters whose counter `name` is at least `value`,
+				// and stop if none are left.
+				Species::IfCounterAtLeast(name, value) => {
+					synapse
+						.casters
+						.retain(|CasterTarget { caster, targets: _ }| {
+							manager
+								.get_character(*caster)
+								.is_some_and(|caster| caster.borrow().counter(name) >= *value)
+						});
+					halted = synapse.casters.is_empty();
+				}
 				// Anoint all creatures of a given Species.
 				Species::SelectSpecies(species) => {
 					// should this be restricted to Z level?
@@ -237,47 +1144,44 @@ pub fn process_axioms(mut synapses: Vec<Synapse>, manager: &Manager) -> Result {
 					for creature in found {
 						synapse
 							.casters
-							.push(CasterTarget::new(creature.clone(), Vec::new()));
+							.push(CasterTarget::new(&creature, TargetSet::default()));
 					}
 				}
 				Species::SelectRealityAnchor => {
-					let player = &manager.reality_anchor;
+					let player = manager.get_reality_anchor();
 					synapse
 						.casters
-						.push(CasterTarget::new(player.clone(), Vec::new()));
+						.push(CasterTarget::new(&player, TargetSet::default()));
 				}
 				// All casters in the synapse turn into targets for the `species`.
 				Species::AnointToTarget(species) => {
 					let mut new_targets = Vec::new();
 					for CasterTarget { caster, targets: _ } in synapse.casters.iter() {
+						let Some(caster) = manager.get_character(*caster) else {
+							continue;
+						};
 						let caster = caster.borrow();
 						new_targets.push((caster.x, caster.y, caster.z)); // Grab the position of every caster
-						drop(caster);
 					}
 					// synapse.casters.clear(); // Remove all casters and their targets
 					// should this be restricted to Z level?
 					let found = manager.get_characters_of_species(*species.clone());
 					for creature in found {
-						synapse.casters.push(CasterTarget {
-							caster: creature.clone(),
-							targets: new_targets.clone(),
-						});
+						synapse.casters.push(CasterTarget::new(
+							&creature,
+							TargetSet::new(new_targets.clone()),
+						));
 					}
 				}
 				// Remove all caster/targets pairs where the caster is `species`.
 				Species::ClearThisCaster(species) => {
-					let mut remove_indices = Vec::new();
-					for (count, CasterTarget { caster, targets: _ }) in
-						synapse.casters.iter().enumerate()
-					{
-						let caster = caster.borrow();
-						if caster.species == *species.clone() {
-							remove_indices.push(count);
-						}
-					}
-					for i in remove_indices {
-						synapse.casters.remove(i);
-					}
+					synapse
+						.casters
+						.retain(|CasterTarget { caster, targets: _ }| {
+							manager
+								.get_character(*caster)
+								.is_none_or(|caster| caster.borrow().species != **species)
+						});
 				}
 				// Target this specific coordinate.
 				Species::SpecificCoord((x, y, z)) => {
@@ -288,43 +1192,34 @@ pub fn process_axioms(mut synapses: Vec<Synapse>, manager: &Manager) -> Result {
 				// Target an adjacent tile to each Caster.
 				Species::CardinalTargeter(dir) => {
 					for CasterTarget { caster, targets } in synapse.casters.iter_mut() {
+						let Some(caster) = manager.get_character(*caster) else {
+							continue;
+						};
 						let caster = caster.borrow();
 						let offset = dir.as_offset();
 						targets.push(map_wrap(caster.x + offset.0, caster.y + offset.1, caster.z));
-						drop(caster);
 					}
 				}
 				// Target the adjacent tile closest to the nearest representative of `species`.
 				Species::PathfindTargeter(species) => {
 					let found = manager.get_characters_of_species(*species.clone());
 					for CasterTarget { caster, targets } in synapse.casters.iter_mut() {
-						let mut chosen = None;
-						let mut distance = i32::MAX;
-						for entity in found.clone() {
-							let candidate = entity.borrow();
-							let cand_coords = (candidate.x, candidate.y, candidate.z);
-							let caster = caster.borrow();
-							let new_dist = // Find the closest representative of Species.
-								manhattan_distance(cand_coords, (caster.x, caster.y, caster.z));
-							if new_dist < distance {
-								chosen = Some(cand_coords);
-								distance = new_dist;
-							}
-						}
-						if let Some(chosen) = chosen {
-							let caster = caster.borrow();
-							let pot_targets = &[
-								(caster.x + 1, caster.y, caster.z),
-								(caster.x - 1, caster.y, caster.z),
-								(caster.x, caster.y - 1, caster.z),
-								(caster.x, caster.y + 1, caster.z),
-							];
-							// TODO: Pathfind through map wrapping too.
-							let pot_targets = filter_targets_by_unoccupied(manager, pot_targets);
-							let new_tar = find_closest_coordinate(&pot_targets, chosen);
-							if let Some(new_tar) = new_tar {
-								targets.push(new_tar);
-							}
+						let Some(caster) = manager.get_character(*caster) else {
+							continue;
+						};
+						let caster = caster.borrow();
+						// A caster does not chase itself,
+						// and representatives on other layers can't be walked to.
+						let goals: Vec<(i32, i32, i32)> = found
+							.iter()
+							.map(|entity| entity.borrow())
+							.filter(|entity| entity.id != caster.id && entity.z == caster.z)
+							.map(|entity| (entity.x, entity.y, entity.z))
+							.collect();
+						if let Some(step) =
+							pathfind::first_step(manager, (caster.x, caster.y, caster.z), &goals)
+						{
+							targets.push(step);
 						}
 					}
 				}
@@ -332,7 +1227,10 @@ pub fn process_axioms(mut synapses: Vec<Synapse>, manager: &Manager) -> Result {
 				Species::PlusTargeter => {
 					let offsets = [(-1, 0), (1, 0), (0, 1), (0, -1)];
 					for CasterTarget { caster, targets } in synapse.casters.iter_mut() {
-						let caster = caster.borrow_mut();
+						let Some(caster) = manager.get_character(*caster) else {
+							continue;
+						};
+						let caster = caster.borrow();
 						for offset in offsets {
 							targets.push(map_wrap(
 								caster.x + offset.0,
@@ -345,6 +1243,9 @@ pub fn process_axioms(mut synapses: Vec<Synapse>, manager: &Manager) -> Result {
 				// Target the tiles on which the Casters stand on.
 				Species::SelfTargeter => {
 					for CasterTarget { caster, targets } in synapse.casters.iter_mut() {
+						let Some(caster) = manager.get_character(*caster) else {
+							continue;
+						};
 						let caster = caster.borrow();
 						targets.push((caster.x, caster.y, caster.z)); // No need for map_wrap, this always stays inbounds
 					}
@@ -352,42 +1253,82 @@ pub fn process_axioms(mut synapses: Vec<Synapse>, manager: &Manager) -> Result {
 				// Target the player's tile.
 				Species::TargetRealityAnchor => {
 					for CasterTarget { caster: _, targets } in synapse.casters.iter_mut() {
-						let player = manager.reality_anchor.borrow();
+						let player = manager.get_reality_anchor();
+						let player = player.borrow();
 						targets.push((player.x, player.y, player.z)); // No need for map_wrap, this always stays inbounds
 					}
 				}
+				// Target every tile each Caster can see within `radius`.
+				Species::VisionTargeter(radius) => {
+					for CasterTarget { caster, targets } in synapse.casters.iter_mut() {
+						let Some(caster) = manager.get_character(*caster) else {
+							continue;
+						};
+						let caster = caster.borrow();
+						targets.extend(fov::visible_tiles(
+							manager,
+							(caster.x, caster.y, caster.z),
+							*radius,
+						));
+					}
+				}
+				// Target a 90° cone of `length` tiles in front of each Caster.
+				Species::ConeTargeter(dir, length) => {
+					let (fx, fy) = dir.as_offset();
+					let length = *length as i32;
+					for CasterTarget { caster, targets } in synapse.casters.iter_mut() {
+						let Some(caster) = manager.get_character(*caster) else {
+							continue;
+						};
+						let caster = caster.borrow();
+						for dy in -length..=length {
+							for dx in -length..=length {
+								// Keep the tiles no more than 45° away from `dir`.
+								let dot = dx * fx + dy * fy;
+								if (dx, dy) != (0, 0)
+									&& dot >= 0 && 2 * dot * dot
+									>= (dx * dx + dy * dy) * (fx * fx + fy * fy)
+								{
+									targets.push(map_wrap(caster.x + dx, caster.y + dy, caster.z));
+								}
+							}
+						}
+					}
+				}
 				// Target tiles with a beam shooting from the Caster in the direction of their momentum.
 				Species::MomentumBeam => {
 					for CasterTarget { caster, targets } in synapse.casters.iter_mut() {
+						let Some(caster) = manager.get_character(*caster) else {
+							continue;
+						};
 						let caster = caster.borrow();
-						let mut beam = beam_from_point(
+						targets.extend(beam_from_point(
 							manager,
-							caster.momentum,
+							caster.momentum.as_angle(),
 							(caster.x, caster.y, caster.z),
 							100,
-						);
-						targets.append(&mut beam);
+						));
 					}
 				}
 				// Target one tile from each Caster in the direction of their momentum.
 				Species::MomentumTouch => {
 					for CasterTarget { caster, targets } in synapse.casters.iter_mut() {
+						let Some(caster) = manager.get_character(*caster) else {
+							continue;
+						};
 						let caster = caster.borrow();
-						let mut beam = beam_from_point(
+						targets.extend(beam_from_point(
 							manager,
-							caster.momentum,
+							caster.momentum.as_angle(),
 							(caster.x, caster.y, caster.z),
 							1,
-						);
-						targets.append(&mut beam);
+						));
 					}
 				}
 				// All Targets's Z coordinates get shifted to `realm`.
 				Species::RealmShift(realm) => {
 					for CasterTarget { caster: _, targets } in synapse.casters.iter_mut() {
-						for tar in targets {
-							tar.2 = *realm;
-						}
+						targets.map(|(x, y, _)| (x, y, *realm));
 					}
 				}
 				// Each target becomes the centre of a circle of `radius`, and is replaced
@@ -395,23 +1336,22 @@ pub fn process_axioms(mut synapses: Vec<Synapse>, manager: &Manager) -> Result {
 				// NOTE: Could be cool to add an `arc_length` for slashes.
 				Species::Orbit(radius) => {
 					for CasterTarget { caster: _, targets } in synapse.casters.iter_mut() {
-						for tar in targets {
-							let mut circle = circle_around(tar, *radius as i32);
+						targets.map(|tar| {
+							let mut circle = circle_around(&tar, *radius as i32);
 							// Sort by clockwise rotation.
 							circle.sort_by(|a, b| {
-								let angle_a = angle_from_center(tar, a);
-								let angle_b = angle_from_center(tar, b);
+								let angle_a = angle_from_center(&tar, a);
+								let angle_b = angle_from_center(&tar, b);
 								angle_a.partial_cmp(&angle_b).unwrap()
 							});
 							let circle: Vec<(i32, i32, i32)> =
 								circle.iter().map(|p| map_wrap(p.0, p.1, p.2)).collect();
 							// "% circle.len()" so that bigger circles are slower to traverse. May need adaptation.
 							let offset = manager.turn_count.borrow().turns % circle.len();
-							let orbit_point = circle
+							*circle
 								.get(offset)
-								.expect("The measured offset was out of bounds");
-							(tar.0, tar.1, tar.2) = (orbit_point.0, orbit_point.1, orbit_point.2);
-						}
+								.expect("The measured offset was out of bounds")
+						});
 					}
 				}
 				// Each target becomes the centre of a circle of `radius`, and is replaced
@@ -419,7 +1359,7 @@ pub fn process_axioms(mut synapses: Vec<Synapse>, manager: &Manager) -> Result {
 				Species::Halo(radius) => {
 					let mut halo = Vec::new();
 					for CasterTarget { caster: _, targets } in synapse.casters.iter_mut() {
-						for tar in &*targets {
+						for tar in targets.tiles() {
 							let mut circle = circle_around(tar, *radius as i32);
 							// Sort by clockwise rotation.
 							circle.sort_by(|a, b| {
@@ -429,100 +1369,356 @@ pub fn process_axioms(mut synapses: Vec<Synapse>, manager: &Manager) -> Result {
 							});
 							halo.append(&mut circle);
 						}
-						targets.clear();
-						targets.append(&mut halo);
+						targets.replace(std::mem::take(&mut halo));
 					}
 				}
 				// Draw lines from the caster to each target, and target everything across
 				// those lines.
 				Species::BeamToTargets => {
-					let mut beams = Vec::new();
 					for CasterTarget { caster, targets } in synapse.casters.iter_mut() {
-						for tar in &*targets {
-							let beam = line_between_two_points(
-								(caster.borrow().x, caster.borrow().y, caster.borrow().z),
-								*tar,
-							);
-							// Remove all duplicates.
-							// FIXME If duplicates get banned all-together, remove this?
-							let mut beam = beam
-								.iter()
-								.filter(|tile| !targets.contains(tile))
-								.copied()
-								.collect();
-							beams.append(&mut beam);
-						}
-						targets.append(&mut beams);
+						let Some(caster) = manager.get_character(*caster) else {
+							continue;
+						};
+						let caster = caster.borrow();
+						let beams: Vec<(i32, i32, i32)> = targets
+							.tiles()
+							.iter()
+							.flat_map(|tar| {
+								line_between_two_points((caster.x, caster.y, caster.z), *tar)
+							})
+							.map(|(x, y, z)| map_wrap(x, y, z))
+							.collect();
+						targets.extend(beams);
+					}
+				}
+				// Stop targeting the tile each Caster stands on.
+				Species::ExcludeSelf => {
+					for CasterTarget { caster: _, targets } in synapse.casters.iter_mut() {
+						targets.add_filter(TargetFilter::ExcludeSelf);
+					}
+				}
+				// Stop targeting tiles on which a piece of `species` stands.
+				Species::ExcludeSpecies(species) => {
+					for CasterTarget { caster: _, targets } in synapse.casters.iter_mut() {
+						targets.add_filter(TargetFilter::ExcludeSpecies(*species.clone()));
+					}
+				}
+				// Only target tiles on which a piece stands.
+				Species::OnlyOccupied => {
+					for CasterTarget { caster: _, targets } in synapse.casters.iter_mut() {
+						targets.add_filter(TargetFilter::OnlyOccupied);
+					}
+				}
+				// Only target tiles on which no piece stands.
+				Species::OnlyEmpty => {
+					for CasterTarget { caster: _, targets } in synapse.casters.iter_mut() {
+						targets.add_filter(TargetFilter::OnlyEmpty);
 					}
 				}
 				// Transform each Target's species into the Caster's species.
 				Species::Twinning => {
 					for CasterTarget { caster, targets } in synapse.casters.iter() {
+						let targets = targets.resolve(manager, *caster);
+						let Some(caster) = manager.get_character(*caster) else {
+							continue;
+						};
 						let cas_species = caster.borrow().species.clone();
-						for (x, y, z) in targets {
+						for (x, y, z) in &targets {
 							if let Some(victim) = manager.get_character_at(*x, *y, *z) {
-								victim.borrow_mut().species = cas_species.clone();
+								if let Some(preview) = manager.preview.borrow_mut().as_mut() {
+									preview.twins.push(((*x, *y, *z), cas_species.clone()));
+									continue;
+								}
+								let victim = victim.borrow().id;
+								apply_or_defer(
+									manager,
+									Effect::Twin {
+										victim,
+										species: cas_species.clone(),
+									},
+								);
 							}
 						}
 					}
 				}
+				// Copy the axioms within `radius` tiles of each Caster onto the empty tiles
+				// around each of its Targets.
+				Species::CopyPaste(radius) => {
+					let radius = *radius as i32;
+					for CasterTarget { caster, targets } in synapse.casters.iter() {
+						let targets = targets.resolve(manager, *caster);
+						let Some(caster) = manager.get_character(*caster) else {
+							continue;
+						};
+						let (cx, cy, cz) = {
+							let caster = caster.borrow();
+							(caster.x, caster.y, caster.z)
+						};
+						let mut characters = Vec::new();
+						for yoff in -radius..=radius {
+							for xoff in -radius..=radius {
+								let (x, y, z) = map_wrap(cx + xoff, cy + yoff, cz);
+								if let Some(piece) = manager.get_character_at(x, y, z) {
+									let species = piece.borrow().species.clone();
+									if species.is_axiom() {
+										characters.push((xoff + radius, yoff + radius, species));
+									}
+								}
+							}
+						}
+						let vault = Vault {
+							width: (radius * 2 + 1) as usize,
+							characters,
+							patrols: HashMap::new(),
+						};
+						for (x, y, z) in &targets {
+							let origin = (x - radius, y - radius, *z);
+							if let Some(preview) = manager.preview.borrow_mut().as_mut() {
+								for (xoff, yoff, species) in &vault.characters {
+									let tile = map_wrap(origin.0 + xoff, origin.1 + yoff, *z);
+									if manager.get_character_at(tile.0, tile.1, tile.2).is_none() {
+										preview.pastes.push((tile, species.clone()));
+									}
+								}
+								continue;
+							}
+							apply_or_defer(
+								manager,
+								Effect::Paste {
+									origin,
+									vault: vault.clone(),
+								},
+							);
+						}
+					}
+				}
 				// Teleport each Caster to its closest Target.
 				Species::Teleport => {
 					for CasterTarget { caster, targets } in synapse.casters.iter() {
-						// Commented because this line prevents collisions from happening.
-						// let targets = filter_targets_by_unoccupied(manager, targets);
-						let b_caster = caster.borrow_mut();
+						let targets = targets.resolve(manager, *caster);
+						let Some(caster) = manager.get_character(*caster) else {
+							continue;
+						};
+						let b_caster = caster.borrow();
 						let (cx, cy, cz) = (b_caster.x, b_caster.y, b_caster.z);
+						let (cas_id, cas_species) = (b_caster.id, b_caster.species.clone());
 						drop(b_caster);
 						if let Some((x, y, z)) = find_closest_coordinate(&targets, (cx, cy, cz)) {
-							// This will return an intentional error if a collision happens.
-							// This collision could be used for a cool Contingency, like starting
-							// dialogue.
-							let _ = manager.teleport_piece(caster, x, y, z);
+							if let Some(preview) = manager.preview.borrow_mut().as_mut() {
+								preview.teleports.push(PlannedTeleport {
+									species: cas_species,
+									from: (cx, cy, cz),
+									to: map_wrap(x, y, z),
+								});
+								continue;
+							}
+							apply_or_defer(
+								manager,
+								Effect::Teleport {
+									piece: cas_id,
+									to: (x, y, z),
+								},
+							);
+						}
+					}
+				}
+				// Add one to each Caster's counter `name`.
+				// Counters are not Effects: they change right away, even in `EffectMode::Deferred`,
+				// so that the gates further along the synapse see the new value.
+				Species::IncrementCounter(name) => {
+					for CasterTarget { caster, targets: _ } in synapse.casters.iter() {
+						if let Some(caster) = manager.get_character(*caster) {
+							*caster
+								.borrow_mut()
+								.properties
+								.entry(name.clone())
+								.or_default() += 1;
+						}
+					}
+				}
+				// Set each Caster's counter `name` back to 0.
+				Species::ResetCounter(name) => {
+					for CasterTarget { caster, targets: _ } in synapse.casters.iter() {
+						if let Some(caster) = manager.get_character(*caster) {
+							caster.borrow_mut().properties.remove(name);
+						}
+					}
+				}
+				// Spawn a piece of `species` on each empty Target.
+				Species::Summon(species) => {
+					for CasterTarget { caster, targets } in synapse.casters.iter() {
+						for tile in filter_targets_by_unoccupied(
+							manager,
+							&targets.resolve(manager, *caster),
+						) {
+							if let Some(preview) = manager.preview.borrow_mut().as_mut() {
+								preview.pastes.push((tile, *species.clone()));
+								continue;
+							}
+							// A summon is a paste of a single piece,
+							// so it follows the same rules when it conflicts with others.
+							apply_or_defer(
+								manager,
+								Effect::Paste {
+									origin: tile,
+									vault: Vault {
+										width: 1,
+										characters: vec![(0, 0, *species.clone())],
+										patrols: HashMap::new(),
+									},
+								},
+							);
 						}
 					}
 				}
-				// Dump the world state to save.toml.
+				// Remove the piece on each Target from the world.
+				// The reality anchor can't be removed.
+				Species::Annihilate => {
+					for CasterTarget { caster, targets } in synapse.casters.iter() {
+						for (x, y, z) in
+							filter_targets_by_occupied(manager, &targets.resolve(manager, *caster))
+						{
+							let piece = manager.get_character_at(x, y, z).unwrap().borrow().id;
+							if let Some(preview) = manager.preview.borrow_mut().as_mut() {
+								if piece != manager.reality_anchor.get() {
+									preview.annihilations.push((x, y, z));
+								}
+								continue;
+							}
+							apply_or_defer(manager, Effect::Annihilate { piece });
+						}
+					}
+				}
+				// Suspend the synapse, and resume it from the next axiom `turns` turns later.
+				Species::Delay(turns) => {
+					delay = Some(*turns);
+				}
+				// Pull the piece on each Target toward its Caster until it is blocked.
+				Species::Pull => {
+					for CasterTarget { caster, targets } in synapse.casters.iter() {
+						let Some(caster_ref) = manager.get_character(*caster) else {
+							continue;
+						};
+						let origin = {
+							let caster = caster_ref.borrow();
+							(caster.x, caster.y, caster.z)
+						};
+						let mut pulled =
+							filter_targets_by_occupied(manager, &targets.resolve(manager, *caster));
+						// The closest pieces go first, so they don't block the others.
+						pulled.sort_by_key(|tile| pathfind::wrapped_distance(*tile, origin));
+						for (x, y, z) in pulled {
+							// Earlier pulls may have set off contingencies that emptied this tile.
+							let Some(piece) = manager.get_character_at(x, y, z) else {
+								continue;
+							};
+							let (piece, species) = {
+								let piece = piece.borrow();
+								(piece.id, piece.species.clone())
+							};
+							if piece == *caster {
+								continue;
+							}
+							if let Some(preview) = manager.preview.borrow_mut().as_mut() {
+								preview.teleports.push(PlannedTeleport {
+									species,
+									from: (x, y, z),
+									to: slide_destination(manager, (x, y, z), |tile| {
+										OrdDir::orthogonal_from_offset(wrapped_offset(tile, origin))
+									}),
+								});
+								continue;
+							}
+							apply_or_defer(
+								manager,
+								Effect::Pull {
+									piece,
+									toward: *caster,
+								},
+							);
+						}
+					}
+				}
+				// Move each Caster away from its closest occupied Target until it is blocked.
+				Species::Dash => {
+					for CasterTarget { caster, targets } in synapse.casters.iter() {
+						let Some(caster_ref) = manager.get_character(*caster) else {
+							continue;
+						};
+						let (origin, species) = {
+							let caster = caster_ref.borrow();
+							((caster.x, caster.y, caster.z), caster.species.clone())
+						};
+						let mut occupied =
+							filter_targets_by_occupied(manager, &targets.resolve(manager, *caster));
+						occupied.retain(|tile| *tile != origin);
+						let Some(away_from) = occupied
+							.into_iter()
+							.min_by_key(|tile| pathfind::wrapped_distance(*tile, origin))
+						else {
+							continue;
+						};
+						if let Some(preview) = manager.preview.borrow_mut().as_mut() {
+							let (dx, dy) = wrapped_offset(origin, away_from);
+							let dir = OrdDir::orthogonal_from_offset((-dx, -dy));
+							preview.teleports.push(PlannedTeleport {
+								species,
+								from: origin,
+								to: slide_destination(manager, origin, |_| dir),
+							});
+							continue;
+						}
+						apply_or_defer(
+							manager,
+							Effect::Dash {
+								piece: *caster,
+								away_from,
+							},
+						);
+					}
+				}
+				// Dump the world state to the manager's save slot.
 				Species::SaveGame => {
-					manager.dump_characters();
+					if let Some(preview) = manager.preview.borrow_mut().as_mut() {
+						preview.save = true;
+					} else {
+						manager.dump_characters();
+					}
 				}
-				// Rewind the world state as it is stored in save.toml.
+				// Rewind the world state as it is stored in the manager's save slot.
 				Species::LoadGame => {
-					if std::path::Path::new("save.toml").exists() {
-						let saved_chars = std::fs::read_to_string("save.toml").unwrap();
-						let saved_manager: SavePayload = toml::from_str(&saved_chars).unwrap();
-						let new_characters = saved_manager.characters.clone();
-						// Find the player among the cloned characters.
-						// We need to do this because the main.rs loop uses as_ptr.
-						let new_anchor = new_characters
-							.iter()
-							.find(|p| {
-								let p = p.borrow();
-								let compare_anchor = saved_manager.reality_anchor.borrow();
-								let (x, y, z) =
-									(compare_anchor.x, compare_anchor.y, compare_anchor.z);
-								// Should it ever be possible for multiple creatures to have the same xyz, this will break.
-								p.x == x && p.y == y && p.z == z
-							})
-							.expect("The player did not exist in the save file")
-							.clone();
-						new_manager = Some(Manager {
-							current_level: manager.current_level.clone(),
-							characters: new_characters,
-							reality_anchor: new_anchor,
-							console: manager.console.clone(),
-							effects: manager.effects.clone(),
-							location: manager.location.clone(),
-							turn_count: RefCell::new(crate::world::TurnCounter {
-								turns: saved_manager.turn_count,
-							}),
-						});
+					let saved = if manager.preview.borrow().is_none() {
+						manager.saved_payload()
+					} else {
+						None
+					};
+					if let Some(saved_manager) = saved {
+						let mut restored = manager.clone();
+						restored.restore(&saved_manager);
+						// OnLoad belongs to the restored world, so it runs there,
+						// but still counts towards the depth of the axioms that loaded it.
+						restored.axiom_depth.set(manager.axiom_depth.get());
+						let loaded = trigger_contingency(&restored, &Species::OnLoad, None);
+						let loaded = loaded.new_manager.unwrap_or(restored);
+						loaded.axiom_depth.set(0);
+						// Let the outermost call report any runaway OnLoad axioms.
+						let overloaded = std::mem::take(&mut *loaded.overloaded.borrow_mut());
+						for pulse in overloaded {
+							if !manager.overloaded.borrow().contains(&pulse) {
+								manager.overloaded.borrow_mut().push(pulse);
+							}
+						}
+						new_manager = Some(loaded);
 					}
 				}
+				// Rewind the world state to the start of the turn `turns` turns ago.
+				Species::Rewind(turns) if manager.preview.borrow().is_none() => {
+					new_manager = manager.rewind(*turns);
+				}
 				// Add a fading tile effect to each Target.
-				Species::Fireworks => {
-					for CasterTarget { caster: _, targets } in synapse.casters.iter() {
-						for tar in targets {
+				Species::Fireworks if manager.preview.borrow().is_none() => {
+					for CasterTarget { caster, targets } in synapse.casters.iter() {
+						for tar in targets.resolve(manager, *caster) {
 							manager.effects.borrow_mut().push(TileEffect {
 								x: tar.0,
 								y: tar.1,
@@ -537,87 +1733,131 @@ pub fn process_axioms(mut synapses: Vec<Synapse>, manager: &Manager) -> Result {
 				Species::SwapAnchor => {
 					for CasterTarget { caster, targets } in synapse.casters.iter() {
 						// Only targets with an entity should be candidates.
-						let targets = filter_targets_by_occupied(manager, targets);
-						let b_caster = caster.borrow();
-						let (cx, cy, cz) = (b_caster.x, b_caster.y, b_caster.z);
+						let targets =
+							filter_targets_by_occupied(manager, &targets.resolve(manager, *caster));
+						let Some(b_caster) = manager.get_character(*caster) else {
+							continue;
+						};
+						let (cx, cy, cz) = {
+							let b_caster = b_caster.borrow();
+							(b_caster.x, b_caster.y, b_caster.z)
+						};
 						// Find the closest entity that's on a target.
 						if let Some((x, y, z)) = find_closest_coordinate(&targets, (cx, cy, cz)) {
-							let anchor_ptr = manager.reality_anchor.as_ptr();
-							drop(b_caster);
-							// If the caster is the anchor, give the anchor to the target.
-							if caster.as_ptr().eq(&anchor_ptr) {
-								let new_anchor = manager.get_character_at(x, y, z).unwrap();
-								if new_anchor.as_ptr() == caster.as_ptr() {
-									// Do not swap the caster with itself - this will crash the game.
-									continue;
-								}
-								manager.reality_anchor.swap(new_anchor);
-							} else if manager
+							let anchor = manager.reality_anchor.get();
+							let target = manager.get_character_at(x, y, z).unwrap().borrow().id;
+							let new_anchor = if *caster == anchor {
+								// If the caster is the anchor, give the anchor to the target.
+								target
+							} else if target == anchor {
 								// But if the target is the anchor, steal their anchor for the caster.
-								.get_character_at(x, y, z)
-								.unwrap()
-								.as_ptr()
-								.eq(&anchor_ptr)
-							{
-								manager.reality_anchor.swap(caster);
+								*caster
+							} else {
+								continue;
+							};
+							if new_anchor == anchor {
+								// The caster targeted itself.
+								continue;
+							}
+							if let Some(preview) = manager.preview.borrow_mut().as_mut() {
+								preview.swaps.push(((cx, cy, cz), (x, y, z)));
+								continue;
 							}
+							apply_or_defer(
+								manager,
+								Effect::SwapAnchor {
+									from: anchor,
+									to: new_anchor,
+								},
+							);
 						}
 					}
 				}
 				Species::TurnIncrementer => {
-					let mut turn_counter = manager.turn_count.borrow_mut();
-					turn_counter.turns += 1;
-					drop(turn_counter);
-					new_manager = trigger_contingency(manager, &Species::OnTurn, None).new_manager;
-				}
-				Species::RadioBroadcaster(output_range) => match output_range {
-					Range::Global(output_message) => {
-						for axiom in &manager.characters {
+					new_manager = increment_turn(manager).new_manager;
+				}
+				// Send a message to every RadioReceiver listening for it, if both of their
+				// ranges allow it.
+				Species::RadioBroadcaster(output_range) => {
+					let broadcaster = (pulse_x, pulse_y, pulse_z);
+					let targets: Vec<(i32, i32, i32)> = synapse
+						.casters
+						.iter()
+						.flat_map(|CasterTarget { caster, targets }| {
+							targets.resolve(manager, *caster)
+						})
+						.collect();
+					// Only flood-filled if a Contained range is involved.
+					let mut circuit = None;
+					let receivers: Vec<((i32, i32, i32), Range)> = manager
+						.characters
+						.borrow()
+						.iter()
+						.filter_map(|axiom| {
 							let axiom = axiom.borrow();
 							if let Species::RadioReceiver(input_range) = &axiom.species {
-								let (synapse_transmission, input_message) = match input_range {
-									Range::Global(input_message) => (
-										vec![Synapse::new(axiom.x, axiom.y, axiom.z)],
-										input_message,
-									),
-									Range::Synaptic(input_message) => (
-										// Continues the synapse to the new destination.
-										vec![Synapse {
-											casters: synapse.casters.clone(),
-											momentum: synapse.momentum,
-											pulse: (axiom.x, axiom.y, axiom.z),
-											visited: synapse.visited.clone(),
-										}],
-										input_message,
-									),
-									_ => todo!(),
-								};
-								let current_z = manager.reality_anchor.borrow().z;
-								if *output_message == *input_message && axiom.z <= current_z {
-									// It can only broadcast to local or upper layers
-
-									// Important to get this axiom out of scope as the new synapse
-									// could use it
-									drop(axiom);
-									new_manager =
-										process_axioms(synapse_transmission, manager).new_manager;
-								}
+								Some(((axiom.x, axiom.y, axiom.z), input_range.clone()))
+							} else {
+								None
 							}
+						})
+						.collect();
+					for (receiver, input_range) in receivers {
+						let current_z = manager.get_reality_anchor().borrow().z;
+						// It can only broadcast to local or upper layers
+						if output_range.message() != input_range.message() || receiver.2 > current_z
+						{
+							continue;
+						}
+						if matches!(output_range, Range::Contained(_))
+							|| matches!(input_range, Range::Contained(_))
+						{
+							circuit.get_or_insert_with(|| contiguous_axiom(manager, broadcaster));
+						}
+						let circuit = circuit.as_deref().unwrap_or_default();
+						if !output_range.reaches(broadcaster, receiver, &targets, circuit)
+							|| !input_range.reaches(broadcaster, receiver, &targets, circuit)
+						{
+							continue;
 						}
+						let synapse_transmission = if matches!(output_range, Range::Synaptic(_))
+							|| matches!(input_range, Range::Synaptic(_))
+						{
+							// Continues the synapse to the new destination.
+							Synapse {
+								casters: synapse.casters.clone(),
+								momentum: synapse.momentum,
+								pulse: receiver,
+								visited: synapse.visited.clone(),
+							}
+						} else {
+							Synapse::new(receiver.0, receiver.1, receiver.2)
+						};
+						new_manager =
+							process_axioms(vec![synapse_transmission], manager).new_manager;
 					}
-					_ => (),
-				},
+				}
 				_ => (), // Any non-Axiom species
 			}
+			if let Some(preview) = manager.preview.borrow_mut().as_mut() {
+				for CasterTarget { caster, targets } in synapse.casters.iter() {
+					for tar in targets.resolve(manager, *caster) {
+						if !preview.targets.contains(&tar) {
+							preview.targets.push(tar);
+						}
+					}
+				}
+			}
 			let mut potential_new_axioms = Vec::new();
 			let search_order = generate_clockwise_rotation(synapse.momentum); // Starting from the direction we come from, rotate clockwise.
 			let search_order_ints = search_order.iter().map(|x| x.as_offset());
 			for (i, adjacency) in search_order_ints.enumerate() {
 				let (new_pulse_x, new_pulse_y, new_pulse_z) =
 					map_wrap(pulse_x + adjacency.0, pulse_y + adjacency.1, pulse_z);
-				if manager // Must contain an entity and not have been visited before.
-					.get_character_at(new_pulse_x, new_pulse_y, new_pulse_z)
-					.is_some() && !synapse
+				if !halted
+					&& manager // Must contain an entity and not have been visited before.
+						.get_character_at(new_pulse_x, new_pulse_y, new_pulse_z)
+						.is_some() && !synapse
 					.visited
 					.contains(&(new_pulse_x, new_pulse_y, new_pulse_z))
 				{
@@ -627,7 +1867,45 @@ pub fn process_axioms(mut synapses: Vec<Synapse>, manager: &Manager) -> Result {
 					));
 				}
 			}
-			if potential_new_axioms.is_empty() {
+			if let Some(trace) = manager.trace.borrow_mut().as_mut() {
+				trace.push(TraceStep {
+					depth,
+					pulse: (pulse_x, pulse_y, pulse_z),
+					axiom: curr_ax_species.clone(),
+					casters: synapse
+						.casters
+						.iter()
+						.filter_map(|CasterTarget { caster, targets }| {
+							let targets = targets.resolve(manager, *caster);
+							let caster = manager.get_character(*caster)?;
+							let caster = caster.borrow();
+							Some(((caster.x, caster.y, caster.z), targets))
+						})
+						.collect(),
+					next: potential_new_axioms.first().map(|(_, pulse)| *pulse),
+					branches: potential_new_axioms
+						.iter()
+						.skip(1)
+						.map(|(_, pulse)| *pulse)
+						.collect(),
+				});
+			}
+			if let Some(turns) = delay {
+				let turn = manager.turn_count.borrow().turns + turns;
+				let mut delayed = manager.delayed.borrow_mut();
+				for (momentum, pulse) in potential_new_axioms {
+					delayed.push(DelayedSynapse {
+						turn,
+						synapse: Synapse {
+							casters: synapse.casters.clone(),
+							momentum: *momentum,
+							pulse,
+							visited: synapse.visited.clone(),
+						},
+					});
+				}
+				synapses_to_remove.push(syn_count);
+			} else if potential_new_axioms.is_empty() {
 				synapses_to_remove.push(syn_count);
 			} else {
 				synapse.momentum = *potential_new_axioms[0].0;
@@ -653,30 +1931,95 @@ pub fn process_axioms(mut synapses: Vec<Synapse>, manager: &Manager) -> Result {
 		for synapse in new_synapses.drain(..) {
 			synapses.push(synapse);
 		}
+
+		if depth == 1 {
+			resolve_effects(manager);
+		}
+	}
+	manager.axiom_depth.set(depth - 1);
+	if depth == 1 && !manager.overloaded.borrow().is_empty() {
+		let overloaded = std::mem::take(&mut *manager.overloaded.borrow_mut());
+		report_overload(manager, &overloaded);
+		let axioms = overloaded
+			.iter()
+			.filter_map(|(x, y, z)| manager.get_character_at(*x, *y, *z))
+			.collect();
+		// Overloads caused by OnOverload itself are still stopped and reported,
+		// but they do not trigger OnOverload again.
+		manager.axiom_depth.set(1);
+		let overload_result = trigger_contingency(
+			manager,
+			&Species::OnOverload,
+			Some(ContingencyPacket::Overload { axioms }),
+		);
+		resolve_effects(manager);
+		manager.axiom_depth.set(0);
+		let overloaded = std::mem::take(&mut *manager.overloaded.borrow_mut());
+		if !overloaded.is_empty() {
+			report_overload(manager, &overloaded);
+		}
+		if overload_result.new_manager.is_some() {
+			new_manager = overload_result.new_manager;
+		}
+	}
+	if depth == 1 {
+		// Anything still queued was left behind by an overload.
+		manager.effect_queue.borrow_mut().clear();
 	}
 	Result { new_manager }
 }
 
+/// Remember the pulse coordinates of runaway synapses,
+/// until the outermost call to `process_axioms` reports them and triggers `OnOverload`.
+fn record_overload(manager: &Manager, synapses: &[Synapse]) {
+	let mut overloaded = manager.overloaded.borrow_mut();
+	for synapse in synapses {
+		let pulse = map_wrap(synapse.pulse.0, synapse.pulse.1, synapse.pulse.2);
+		if !overloaded.contains(&pulse) {
+			overloaded.push(pulse);
+		}
+	}
+}
+
+/// Print the pulse coordinates of runaway synapses to the console.
+fn report_overload(manager: &Manager, pulses: &[(i32, i32, i32)]) {
+	manager.console.borrow_mut().print_danger(format!(
+		"Axiom overload! Runaway synapses were stopped at {pulses:?}"
+	));
+}
+
 fn manhattan_distance(a: (i32, i32, i32), b: (i32, i32, i32)) -> i32 {
 	(a.0 - b.0).abs() + (a.1 - b.1).abs() + (a.2 - b.2).abs()
 }
 
-/// From `start`, find the four rotations in a clockwise direction.
-fn generate_clockwise_rotation(start: OrdDir) -> [OrdDir; 4] {
-	fn rotate(dir: OrdDir) -> OrdDir {
-		match dir {
-			OrdDir::Up => OrdDir::Right,
-			OrdDir::Right => OrdDir::Down,
-			OrdDir::Down => OrdDir::Left,
-			OrdDir::Left => OrdDir::Up,
+/// Find every tile of the contiguous axiom containing `origin`,
+/// following orthogonally adjacent pieces on the same layer.
+fn contiguous_axiom(manager: &Manager, origin: (i32, i32, i32)) -> Vec<(i32, i32, i32)> {
+	let origin = map_wrap(origin.0, origin.1, origin.2);
+	let mut circuit = vec![origin];
+	let mut i = 0;
+	while let Some(&(x, y, z)) = circuit.get(i) {
+		for dir in OrdDir::ORTHOGONAL {
+			let (dx, dy) = dir.as_offset();
+			let neighbour = map_wrap(x + dx, y + dy, z);
+			if !circuit.contains(&neighbour)
+				&& manager
+					.get_character_at(neighbour.0, neighbour.1, neighbour.2)
+					.is_some()
+			{
+				circuit.push(neighbour);
+			}
 		}
+		i += 1;
 	}
-	[
-		start,
-		rotate(start),
-		rotate(rotate(start)),
-		rotate(rotate(rotate(start))),
-	]
+	circuit
+}
+
+/// From `start`, find the four quarter turns in a clockwise direction.
+///
+/// Synapses only travel orthogonally, so `start` should not be diagonal.
+fn generate_clockwise_rotation(start: OrdDir) -> [OrdDir; 4] {
+	[0, 2, 4, 6].map(|eighths| start.rotate_clockwise(eighths))
 }
 
 /// Find the tile with the shortest Manhattan distance to `target`.
@@ -762,32 +2105,6 @@ fn filter_targets_by_occupied(
 		.collect()
 }
 
-fn line_between_two_points(start: (i32, i32, i32), end: (i32, i32, i32)) -> Vec<(i32, i32, i32)> {
-	let (dx, dy) = (end.0 - start.0, end.1 - start.1);
-	let (nx, ny) = (dx.abs(), dy.abs());
-	let (sign_x, sign_y) = (dx.signum(), dy.signum());
-
-	let mut p = (start.0, start.1, start.2);
-	let mut points = vec![p];
-	let mut ix = 0;
-	let mut iy = 0;
-	while ix < nx || iy < ny {
-		let step_x = (0.5 + ix as f32) / nx as f32;
-		let step_y = (0.5 + iy as f32) / ny as f32;
-		if step_x < step_y {
-			// next step is horizontal
-			p.0 += sign_x;
-			ix += 1;
-		} else {
-			// next step is vertical
-			p.1 += sign_y;
-			iy += 1;
-		}
-		points.push(p);
-	}
-	points
-}
-
 /// Return all tiles in the path of a beam that stops at the first encountered creature.
 fn beam_from_point(
 	manager: &Manager,
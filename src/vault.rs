@@ -1,19 +1,24 @@
 use crate::{
-	character::OrdDir,
+	character::{OrdDir, Patrol},
 	spell::{Range, Species},
 };
 use std::{collections::HashMap, fs, path::Path};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Vault {
 	pub width: usize,
 
 	pub characters: Vec<(i32, i32, Species)>,
+	/// The routes of the characters standing on these tiles.
+	pub patrols: HashMap<(i32, i32), Patrol>,
 }
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Metadata {
 	symbols: HashMap<char, Species>,
+	/// Every character drawn with one of these symbols follows this route.
+	#[serde(default)]
+	patrols: HashMap<char, Patrol>,
 }
 
 #[derive(Clone, Debug, thiserror::Error)]
@@ -31,9 +36,17 @@ impl Vault {
 	///
 	/// Returns an error if the file could not be opened or parsed.
 	pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
-		let mut width = 0;
-
 		let vault_text = fs::read_to_string(path).unwrap();
+		Self::parse(&vault_text)
+	}
+
+	/// Build a vault from the contents of a `.vault` file, without touching the disk.
+	///
+	/// # Errors
+	///
+	/// Returns an error if the text could not be parsed.
+	pub fn parse(vault_text: &str) -> Result<Self, Error> {
+		let mut width = 0;
 
 		// FIXME: Make this return 1 variable.
 		let (symbols, layout) = vault_text
@@ -48,11 +61,15 @@ impl Vault {
 		let metadata: Metadata = toml::from_str(symbols)?;
 
 		let mut characters = Vec::new();
+		let mut patrols = HashMap::new();
 
 		for (y, line) in layout.lines().enumerate() {
 			for (x, c) in line.chars().enumerate() {
 				if let Some(symbol) = metadata.symbols.get(&c) {
-					characters.push((x as i32, y as i32, symbol.clone()))
+					characters.push((x as i32, y as i32, symbol.clone()));
+					if let Some(patrol) = metadata.patrols.get(&c) {
+						patrols.insert((x as i32, y as i32), patrol.clone());
+					}
 				} else {
 					if c != ' ' {
 						return Err(Error::UnexpectedSymbol(c));
@@ -61,6 +78,10 @@ impl Vault {
 			}
 		}
 
-		Ok(Self { width, characters })
+		Ok(Self {
+			width,
+			characters,
+			patrols,
+		})
 	}
 }
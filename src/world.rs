@@ -1,14 +1,21 @@
-use spell::{trigger_contingency, ContingencyPacket};
+use spell::{trigger_contingency, trigger_tile_entry, ContingencyPacket};
 
-use crate::character::OrdDir;
+use crate::character::{EntityId, OrdDir, Patrol, MISSING_ID};
+use crate::pathfind;
 use crate::prelude::*;
-use std::cell::RefCell;
+use grid::Grid;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::rc::Rc;
 
 use self::animation::TileEffect;
 use self::spell::Species;
 
 pub const WORLD_ROWS: usize = 45;
 pub const WORLD_COLS: usize = 45;
+/// How many turns can be rewound before the oldest snapshots are forgotten.
+pub const HISTORY_LENGTH: usize = 256;
 
 pub type CharacterRef = std::rc::Rc<RefCell<character::Piece>>;
 
@@ -21,14 +28,149 @@ pub struct Manager {
 	/// This is the level pointed to by `location.level`.
 	pub current_level: Level,
 	// It might be useful to sort this by remaining action delay to make selecting the next character easier.
-	pub characters: Vec<CharacterRef>,
+	/// Axioms may spawn pieces while others are iterating over this,
+	/// so loops that can run axioms should iterate over a copy.
+	pub characters: RefCell<Vec<CharacterRef>>,
+	/// Which characters stand on each tile.
+	/// This must be kept in sync whenever a character is spawned, moved or despawned.
+	pub occupancy: RefCell<Occupancy>,
 	// The current player on which the world focuses on
-	pub reality_anchor: CharacterRef,
+	pub reality_anchor: Cell<EntityId>,
+	/// The ID that will be given to the next spawned piece.
+	pub next_id: Cell<EntityId>,
 	/// Always point to the party's pieces, even across floors.
 	/// When exiting a dungeon, these sheets will be saved to a party struct.
-	pub console: Console,
+	pub console: RefCell<Console>,
 	pub effects: RefCell<Vec<TileEffect>>,
 	pub turn_count: RefCell<TurnCounter>,
+	/// A snapshot of the world at the start of each turn, oldest first.
+	pub history: RefCell<Vec<SavePayload>>,
+	/// If this is set, Functions are recorded here instead of being applied (see `spell::preview_keypress`).
+	pub preview: RefCell<Option<spell::Preview>>,
+	/// How many calls to `process_axioms` are currently running, nested inside each other.
+	pub axiom_depth: Cell<usize>,
+	/// Pulse coordinates of runaway synapses, waiting for `OnOverload` to be triggered.
+	pub overloaded: RefCell<Vec<(i32, i32, i32)>>,
+	/// If this is set, every step taken by a synapse is recorded here.
+	pub trace: RefCell<Option<Vec<spell::TraceStep>>>,
+	pub effect_mode: spell::EffectMode,
+	/// Effects waiting for the end of the current wave, if `effect_mode` is `Deferred`.
+	pub effect_queue: RefCell<Vec<spell::Effect>>,
+	/// Synapses suspended by `Delay`, resumed by `spell::increment_turn`.
+	pub delayed: RefCell<Vec<spell::DelayedSynapse>>,
+	/// Where `SaveGame` writes and `LoadGame` reads.
+	pub save_slot: SaveSlot,
+	/// Set while `Mirror`s are repeating the reality anchor's step, so that their own moves
+	/// can't set off another round of mirroring.
+	pub mirroring: Cell<bool>,
+}
+
+/// An index of the pieces standing on each tile, with one grid per layer.
+///
+/// This makes finding the piece on a tile independent of the number of characters.
+#[derive(Clone, Debug, Default)]
+pub struct Occupancy {
+	layers: HashMap<i32, Grid<Vec<CharacterRef>>>,
+	entities: HashMap<EntityId, CharacterRef>,
+	/// The `OnEnterTile` contingencies watching each tile.
+	watchers: HashMap<(i32, i32, i32), Vec<CharacterRef>>,
+	/// The tile watched by each `OnEnterTile` contingency, so it can be found again once it moves.
+	watched: HashMap<EntityId, (i32, i32, i32)>,
+}
+
+impl Occupancy {
+	pub fn new(characters: &[CharacterRef]) -> Self {
+		let mut occupancy = Self::default();
+		for character in characters {
+			occupancy.insert(character);
+		}
+		occupancy
+	}
+
+	/// Register `character` on the tile it currently stands on.
+	pub fn insert(&mut self, character: &CharacterRef) {
+		let (x, y, z) = {
+			let character = character.borrow();
+			(character.x, character.y, character.z)
+		};
+		let layer = self
+			.layers
+			.entry(z)
+			.or_insert_with(|| Grid::new(WORLD_ROWS, WORLD_COLS));
+		if let Some(tile) = layer.get_mut(y, x) {
+			tile.push(character.clone());
+		}
+		let (id, species) = {
+			let character = character.borrow();
+			(character.id, character.species.clone())
+		};
+		if let Species::OnEnterTile((dx, dy, dz)) = species {
+			let watched = map_wrap(x + dx, y + dy, z + dz);
+			self.watchers
+				.entry(watched)
+				.or_default()
+				.push(character.clone());
+			self.watched.insert(id, watched);
+		}
+		self.entities.insert(id, character.clone());
+	}
+
+	/// Unregister `character` from the tile at `(x, y, z)`, which should be where it was last inserted.
+	pub fn remove(&mut self, character: &CharacterRef, x: i32, y: i32, z: i32) {
+		if let Some(tile) = self
+			.layers
+			.get_mut(&z)
+			.and_then(|layer| layer.get_mut(y, x))
+		{
+			tile.retain(|other| !std::rc::Rc::ptr_eq(other, character));
+		}
+		if let Some(watched) = self.watched.remove(&character.borrow().id) {
+			if let Some(watchers) = self.watchers.get_mut(&watched) {
+				watchers.retain(|other| !std::rc::Rc::ptr_eq(other, character));
+			}
+		}
+	}
+
+	/// Unregister `character` entirely, as if it had never been inserted.
+	pub fn forget(&mut self, character: &CharacterRef) {
+		let (id, x, y, z) = {
+			let character = character.borrow();
+			(character.id, character.x, character.y, character.z)
+		};
+		self.remove(character, x, y, z);
+		self.entities.remove(&id);
+	}
+
+	pub fn get(&self, x: i32, y: i32, z: i32) -> Option<&CharacterRef> {
+		self.layers.get(&z)?.get(y, x)?.first()
+	}
+
+	pub fn get_entity(&self, id: EntityId) -> Option<&CharacterRef> {
+		self.entities.get(&id)
+	}
+
+	/// The `OnEnterTile` contingencies watching the tile at `(x, y, z)`.
+	pub fn watchers(&self, x: i32, y: i32, z: i32) -> &[CharacterRef] {
+		self.watchers
+			.get(&(x, y, z))
+			.map_or(&[], |watchers| watchers.as_slice())
+	}
+}
+
+/// Where the world state is kept by `SaveGame`, until `LoadGame` reads it back.
+#[derive(Clone, Debug)]
+pub enum SaveSlot {
+	/// A TOML file, which is what the game itself uses.
+	File(PathBuf),
+	/// A save kept in memory and shared by every copy of the manager, like a file would be.
+	/// Simulations and replays use this, so that they never touch the player's save file.
+	Memory(Rc<RefCell<Option<SavePayload>>>),
+}
+
+impl Default for SaveSlot {
+	fn default() -> Self {
+		Self::Memory(Rc::default())
+	}
 }
 
 /// Contains information about what should generate on each floor.
@@ -41,8 +183,12 @@ pub struct Level {
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct SavePayload {
 	pub characters: Vec<CharacterRef>,
-	pub reality_anchor: CharacterRef,
+	pub reality_anchor: EntityId,
 	pub turn_count: usize,
+	#[serde(default)]
+	pub effect_mode: spell::EffectMode,
+	#[serde(default)]
+	pub delayed: Vec<spell::DelayedSynapse>,
 }
 
 /// The total number of turns elapsed, incremented with TurnIncrementer.
@@ -70,65 +216,403 @@ pub struct Location {
 }
 
 impl Manager {
+	/// Create an empty world containing only `player`, which becomes the reality anchor.
+	pub fn new(player: character::Piece) -> Self {
+		let manager = Self {
+			location: Location {
+				level: String::from("New Level"),
+				floor: 0,
+			},
+			console: RefCell::new(Console::default()),
+			reality_anchor: Cell::new(0),
+			next_id: Cell::new(0),
+
+			current_level: Level::default(),
+			occupancy: RefCell::new(Occupancy::default()),
+			characters: RefCell::new(Vec::new()),
+			effects: RefCell::new(Vec::new()),
+			turn_count: RefCell::new(TurnCounter { turns: 0 }),
+			history: RefCell::new(Vec::new()),
+			preview: RefCell::new(None),
+			axiom_depth: Cell::new(0),
+			overloaded: RefCell::new(Vec::new()),
+			trace: RefCell::new(None),
+			effect_mode: spell::EffectMode::default(),
+			effect_queue: RefCell::new(Vec::new()),
+			delayed: RefCell::new(Vec::new()),
+			save_slot: SaveSlot::default(),
+			mirroring: Cell::new(false),
+		};
+		let player = manager.spawn(player);
+		manager.reality_anchor.set(player.borrow().id);
+		manager
+	}
+
+	/// Give `piece` a fresh ID and add it to the world.
+	///
+	/// Segments join the body they line up with.
+	pub fn spawn(&self, mut piece: character::Piece) -> CharacterRef {
+		piece.id = self.next_id.get();
+		self.next_id.set(piece.id + 1);
+		let piece = std::rc::Rc::new(RefCell::new(piece));
+		self.occupancy.borrow_mut().insert(&piece);
+		self.characters.borrow_mut().push(piece.clone());
+		self.link_segments(&piece);
+		piece
+	}
+
+	/// Attach `piece` to the end of the body it continues, if it is a segment, then give that body
+	/// every free segment lined up behind it: segment 1 next to the head, segment 2 next to segment 1,
+	/// and so on, across the map's edges if need be.
+	///
+	/// Pieces can be linked in any order, as the head or any segment may come first.
+	pub fn link_segments(&self, piece: &CharacterRef) {
+		let Some(index) = piece.borrow().species.segment_index() else {
+			return;
+		};
+		let heads: Vec<CharacterRef> = self
+			.characters
+			.borrow()
+			.iter()
+			.filter(|other| other.borrow().species.segment_index() == Some(0))
+			.cloned()
+			.collect();
+		let mut claimed: HashSet<EntityId> = heads
+			.iter()
+			.flat_map(|head| head.borrow().segments.clone())
+			.collect();
+		// The last piece of a body, which the next segment must stand next to.
+		let end_of = |head: &CharacterRef| {
+			let segments = head.borrow().segments.clone();
+			let end = segments
+				.last()
+				.and_then(|id| self.get_character(*id))
+				.unwrap_or_else(|| head.clone());
+			let end = end.borrow();
+			(end.x, end.y, end.z)
+		};
+		let position = {
+			let piece = piece.borrow();
+			(piece.x, piece.y, piece.z)
+		};
+		let head = if index == 0 {
+			piece.clone()
+		} else if claimed.contains(&piece.borrow().id) {
+			return;
+		} else {
+			let Some(head) = heads.into_iter().find(|head| {
+				let end = end_of(head);
+				let (dx, dy) = wrapped_offset(end, position);
+				head.borrow().segments.len() == index - 1
+					&& end.2 == position.2
+					&& dx.abs() <= 1
+					&& dy.abs() <= 1
+			}) else {
+				return;
+			};
+			head
+		};
+		let mut segments = head.borrow().segments.clone();
+		let mut end = end_of(&head);
+		loop {
+			let wanted = Some(segments.len() + 1);
+			let next = OrdDir::ALL.iter().find_map(|dir| {
+				let (dx, dy) = dir.as_offset();
+				let (x, y, z) = map_wrap(end.0 + dx, end.1 + dy, end.2);
+				let next = self.get_character_at(x, y, z)?;
+				let next_ref = next.borrow();
+				(next_ref.species.segment_index() == wanted && !claimed.contains(&next_ref.id))
+					.then(|| next.clone())
+			});
+			let Some(next) = next else {
+				break;
+			};
+			let next = next.borrow();
+			claimed.insert(next.id);
+			segments.push(next.id);
+			end = (next.x, next.y, next.z);
+		}
+		head.borrow_mut().segments = segments;
+	}
+
+	/// Take `piece` out of any body it belongs to, along with the segments behind it,
+	/// and break up its own body. This is needed when it stops being the segment it was.
+	pub fn unlink_segments(&self, piece: &CharacterRef) {
+		let id = {
+			let mut piece = piece.borrow_mut();
+			piece.segments.clear();
+			piece.id
+		};
+		for head in self.characters.borrow().iter() {
+			if std::rc::Rc::ptr_eq(head, piece) {
+				continue;
+			}
+			let mut head = head.borrow_mut();
+			if let Some(cut) = head.segments.iter().position(|segment| *segment == id) {
+				head.segments.truncate(cut);
+			}
+		}
+	}
+
+	/// Remove the piece `id` from the world, and return it.
+	///
+	/// Anything still referring to `id` will simply stop finding it.
+	pub fn despawn(&self, id: EntityId) -> Option<CharacterRef> {
+		let piece = self.get_character(id)?;
+		self.occupancy.borrow_mut().forget(&piece);
+		self.characters
+			.borrow_mut()
+			.retain(|other| !std::rc::Rc::ptr_eq(other, &piece));
+		Some(piece)
+	}
+
+	/// Write the world state to `save_slot`.
 	pub fn dump_characters(&self) {
-		let output = toml::to_string(&SavePayload {
-			characters: self.characters.clone(),
-			reality_anchor: self.reality_anchor.clone(),
+		match &self.save_slot {
+			SaveSlot::File(path) => {
+				let output = toml::to_string(&self.save_payload()).unwrap();
+				std::fs::write(path, output).unwrap();
+			}
+			SaveSlot::Memory(save) => *save.borrow_mut() = Some(self.save_payload()),
+		}
+	}
+
+	/// Read back the world state stored in `save_slot`, if anything was saved there.
+	///
+	/// A save file that cannot be read, or whose reality anchor is missing,
+	/// is ignored with a warning on the console.
+	/// Pieces saved without an ID are given fresh ones.
+	pub fn saved_payload(&self) -> Option<SavePayload> {
+		let payload = match &self.save_slot {
+			SaveSlot::File(path) => {
+				if !path.exists() {
+					return None;
+				}
+				let parsed = std::fs::read_to_string(path)
+					.map_err(|err| err.to_string())
+					.and_then(|text| {
+						toml::from_str::<SavePayload>(&text).map_err(|err| err.to_string())
+					});
+				match parsed {
+					Ok(payload) => payload,
+					Err(err) => {
+						self.console.borrow_mut().print_danger(format!(
+							"The save file {} could not be read, and was ignored: {err}",
+							path.display()
+						));
+						return None;
+					}
+				}
+			}
+			SaveSlot::Memory(save) => save.borrow().clone()?,
+		};
+		let mut next_id = payload
+			.characters
+			.iter()
+			.map(|p| p.borrow().id)
+			.filter(|id| *id != MISSING_ID)
+			.map(|id| id + 1)
+			.max()
+			.unwrap_or(0);
+		for piece in &payload.characters {
+			let mut piece = piece.borrow_mut();
+			if piece.id == MISSING_ID {
+				piece.id = next_id;
+				next_id += 1;
+			}
+		}
+		if !payload
+			.characters
+			.iter()
+			.any(|p| p.borrow().id == payload.reality_anchor)
+		{
+			self.console
+				.borrow_mut()
+				.print_danger("The save file has no reality anchor, and was ignored.");
+			return None;
+		}
+		Some(payload)
+	}
+
+	/// Take a copy of the world state.
+	///
+	/// The characters are cloned rather than shared,
+	/// so the payload stays the same as the game goes on.
+	pub fn save_payload(&self) -> SavePayload {
+		let characters: Vec<CharacterRef> = self
+			.characters
+			.borrow()
+			.iter()
+			.map(|p| std::rc::Rc::new(RefCell::new(p.borrow().clone())))
+			.collect();
+		SavePayload {
+			characters,
+			reality_anchor: self.reality_anchor.get(),
 			turn_count: self.turn_count.borrow().turns,
-		})
-		.unwrap();
-		std::fs::write("save.toml", output).unwrap();
+			effect_mode: self.effect_mode,
+			delayed: self.delayed.borrow().clone(),
+		}
 	}
 
-	pub fn locate_player(&self, payload: &SavePayload) -> Option<&CharacterRef> {
-		self.characters.iter().find(|p| {
-			let p = p.borrow();
-			let compare_anchor = payload.reality_anchor.borrow();
-			let (x, y, z) = (compare_anchor.x, compare_anchor.y, compare_anchor.z);
-			// Should it ever be possible for multiple creatures to have the same xyz, this will break.
-			p.x == x && p.y == y && p.z == z
-		})
+	/// Rewind the world state to the one stored in `payload`.
+	///
+	/// Like `save_payload`, the characters are copied so that `payload` can be restored again later.
+	pub fn restore(&mut self, payload: &SavePayload) {
+		self.characters = RefCell::new(
+			payload
+				.characters
+				.iter()
+				.map(|p| std::rc::Rc::new(RefCell::new(p.borrow().clone())))
+				.collect(),
+		);
+		self.reindex();
+		assert!(
+			self.get_character(payload.reality_anchor).is_some(),
+			"The player did not exist in the save file"
+		);
+		self.reality_anchor = Cell::new(payload.reality_anchor);
+		self.next_id = Cell::new(
+			self.characters
+				.borrow()
+				.iter()
+				.map(|p| p.borrow().id + 1)
+				.max()
+				.unwrap_or(0),
+		);
+		self.turn_count = RefCell::new(TurnCounter {
+			turns: payload.turn_count,
+		});
+		// This may be a copy of a manager that was in the middle of processing axioms.
+		self.axiom_depth = Cell::new(0);
+		self.overloaded = RefCell::new(Vec::new());
+		self.effect_mode = payload.effect_mode;
+		self.effect_queue = RefCell::new(Vec::new());
+		self.delayed = RefCell::new(payload.delayed.clone());
+		self.mirroring = Cell::new(false);
+	}
+
+	/// Take a snapshot of the world if none exists yet for the current turn.
+	///
+	/// This should be called before any input is processed,
+	/// so that each snapshot holds the state at the start of its turn.
+	pub fn record_history(&self) {
+		let turns = self.turn_count.borrow().turns;
+		let mut history = self.history.borrow_mut();
+		// Forget about any future that was undone.
+		history.retain(|snapshot| snapshot.turn_count <= turns);
+		if history
+			.last()
+			.is_some_and(|snapshot| snapshot.turn_count == turns)
+		{
+			return;
+		}
+		history.push(self.save_payload());
+		if history.len() > HISTORY_LENGTH {
+			history.remove(0);
+		}
 	}
 
-	pub fn next_character(&self) -> &CharacterRef {
-		&self.characters[0]
+	/// Return a copy of the world as it was at the start of the turn `turns` turns ago.
+	///
+	/// If the history does not go back that far, the oldest known turn is used instead.
+	/// Returns `None` if there is no history at all.
+	pub fn rewind(&self, turns: usize) -> Option<Manager> {
+		let target = self.turn_count.borrow().turns.saturating_sub(turns);
+		let history = self.history.borrow();
+		let snapshot = history
+			.iter()
+			.rev()
+			.find(|snapshot| snapshot.turn_count <= target)
+			.or(history.first())?;
+		let mut rewound = self.clone();
+		rewound.restore(snapshot);
+		rewound
+			.history
+			.borrow_mut()
+			.retain(|other| other.turn_count <= snapshot.turn_count);
+		Some(rewound)
+	}
+
+	/// Rebuild the occupancy index from scratch.
+	pub fn reindex(&self) {
+		*self.occupancy.borrow_mut() = Occupancy::new(&self.characters.borrow());
+	}
+
+	pub fn next_character(&self) -> CharacterRef {
+		self.characters.borrow()[0].clone()
+	}
+
+	pub fn get_character(&self, id: EntityId) -> Option<CharacterRef> {
+		self.occupancy.borrow().get_entity(id).cloned()
+	}
+
+	pub fn get_reality_anchor(&self) -> CharacterRef {
+		self.get_character(self.reality_anchor.get())
+			.expect("The reality anchor is not among the characters")
 	}
 
 	// Returns none if no entity is at the specified coordinates.
-	pub fn get_character_at(&self, x: i32, y: i32, z: i32) -> Option<&CharacterRef> {
-		self.characters.iter().find(|p| {
-			let p = p.borrow();
-			p.x == x && p.y == y && p.z == z
-		})
+	pub fn get_character_at(&self, x: i32, y: i32, z: i32) -> Option<CharacterRef> {
+		self.occupancy.borrow().get(x, y, z).cloned()
 	}
 
-	pub fn get_characters_of_species(
-		&self,
-		species: Species,
-	) -> impl Iterator<Item = &CharacterRef> + Clone {
-		self.characters.iter().filter(move |p| {
-			let p = p.borrow();
-			p.species == species
-		})
+	pub fn get_characters_of_species(&self, species: Species) -> Vec<CharacterRef> {
+		self.characters
+			.borrow()
+			.iter()
+			.filter(|p| p.borrow().species == species)
+			.cloned()
+			.collect()
 	}
 
-	pub fn apply_vault(
-		&mut self,
-		x: i32,
-		y: i32,
-		z: i32,
-		vault: &Vault,
-		resources: &ResourceManager,
-	) {
+	/// Spawn the pieces of `vault` with its top-left corner at the given coordinates.
+	pub fn apply_vault(&self, x: i32, y: i32, z: i32, vault: &Vault) {
+		self.spawn_vault(x, y, z, vault, false);
+	}
+
+	/// Like `apply_vault`, but wrap the vault around the edges of the map,
+	/// and leave out pieces that would land on an occupied tile.
+	pub fn paste_vault(&self, x: i32, y: i32, z: i32, vault: &Vault) {
+		self.spawn_vault(x, y, z, vault, true);
+	}
+
+	fn spawn_vault(&self, x: i32, y: i32, z: i32, vault: &Vault, paste: bool) {
+		let place = |xoff: i32, yoff: i32| {
+			if paste {
+				map_wrap(x + xoff, y + yoff, z)
+			} else {
+				(x + xoff, y + yoff, z)
+			}
+		};
 		for (xoff, yoff, species) in &vault.characters {
-			let piece = character::Piece {
-				x: x + xoff,
-				y: y + yoff,
-				z,
+			let (px, py, pz) = place(*xoff, *yoff);
+			if paste && self.get_character_at(px, py, pz).is_some() {
+				continue;
+			}
+			// Waypoints are written relative to the vault, like its characters.
+			let patrol = vault
+				.patrols
+				.get(&(*xoff, *yoff))
+				.map(|patrol| match patrol {
+					Patrol::Waypoints(waypoints) => Patrol::Waypoints(
+						waypoints
+							.iter()
+							.map(|(wx, wy)| {
+								let (wx, wy, _) = place(*wx, *wy);
+								(wx, wy)
+							})
+							.collect(),
+					),
+					Patrol::Bounce(dir) => Patrol::Bounce(*dir),
+				});
+			self.spawn(character::Piece {
+				x: px,
+				y: py,
+				z: pz,
 				species: species.clone(),
-				..character::Piece::new(resources.get_sheet("luvui").unwrap().clone(), resources)
-			};
-			self.characters.push(std::rc::Rc::new(RefCell::new(piece)));
+				patrol,
+				..Default::default()
+			});
 		}
 	}
 }
@@ -184,45 +668,264 @@ impl Manager {
 		y: i32,
 		z: i32,
 	) -> Result<MovementResult, MovementError> {
-		// TODO Preventing the momentum from being warped by the mapwrap
-		// by keeping the original coords.
 		let (x, y, z) = map_wrap(x, y, z);
 		if let Some(collision) = self.get_character_at(x, y, z) {
+			// Pushing the pieces in the way out of it frees the tile.
+			// Their contingencies wait until the pusher has moved in.
+			if let Some(pushed) = self.push(character_ref, &collision, (x, y, z)) {
+				let result = self.teleport_piece(character_ref, x, y, z);
+				for (piece, species) in pushed {
+					let tile = {
+						let piece = piece.borrow();
+						(piece.x, piece.y, piece.z)
+					};
+					trigger_tile_entry(self, tile, &piece);
+					trigger_contingency(
+						self,
+						&Species::OnPush(Box::new(species)),
+						Some(ContingencyPacket::Push {
+							pushed: piece,
+							pusher: character_ref.clone(),
+						}),
+					);
+				}
+				return result;
+			}
 			let mut character = character_ref.borrow_mut();
 			if (character.x, character.y, character.z) == (x, y, z) {
 				// Prevent entities from colliding with themselves.
 				return Err(MovementError::HitWall);
 			}
 			let mut coll_character = collision.borrow_mut();
-			let (ix, iy) = (character.x, character.y);
-			let (dx, dy) = ((x - ix) as f64, (y - iy) as f64);
+			// Measured the short way around, so that crossing the map's edges doesn't flip it.
+			let offset = wrapped_offset((character.x, character.y, character.z), (x, y, z));
 			// Both the character and the thing being pushed have their momentums changed.
-			character.momentum = dy.atan2(dx);
-			coll_character.momentum = dy.atan2(dx);
+			if let Some(momentum) = OrdDir::from_offset(offset) {
+				character.momentum = momentum;
+				coll_character.momentum = momentum;
+			}
 			let collided_species = coll_character.species.clone();
+			let own_body = character.segments.contains(&coll_character.id);
 			drop(character);
 			drop(coll_character);
+			let packet = ContingencyPacket::Collision {
+				collided: collision.clone(),
+				collider: character_ref.clone(),
+			};
+			if own_body {
+				trigger_contingency(self, &Species::OnSelfCollision, Some(packet.clone()));
+			}
 			trigger_contingency(
 				self,
 				&Species::OnCollision(Box::new(collided_species)),
-				Some(ContingencyPacket::Collision {
-					collided: collision.clone(),
-					collider: character_ref.clone(),
-				}),
+				Some(packet),
 			);
 			// TODO outsource all this collision logic to MovementError in Teleport?
 			Err(MovementError::HitWall)
 		} else {
-			let mut character = character_ref.borrow_mut();
-			let (ix, iy) = (character.x, character.y);
-			character.x = x;
-			character.y = y;
-			character.z = z;
-			let (dx, dy) = ((x - ix) as f64, (y - iy) as f64);
-			character.momentum = dy.atan2(dx);
+			let (ix, iy, iz) = self.place(character_ref, (x, y, z));
+			let segments = character_ref.borrow().segments.clone();
+			self.drag_segments(&segments, (ix, iy, iz));
+			trigger_tile_entry(self, (x, y, z), character_ref);
+			// Only single steps on the same layer are mirrored, not teleports.
+			let offset = wrapped_offset((ix, iy, iz), (x, y, z));
+			if character_ref.borrow().id == self.reality_anchor.get()
+				&& iz == z && offset.0.abs() <= 1
+				&& offset.1.abs() <= 1
+			{
+				if let Some(dir) = OrdDir::from_offset(offset) {
+					self.mirror_anchor(dir);
+				}
+			}
 			Ok(MovementResult::Move)
 		}
 	}
+
+	/// Make every `Mirror` repeat, reflected, the step the reality anchor just took in direction `dir`.
+	///
+	/// Anything the mirrors' own moves cause the anchor to do is not mirrored again.
+	fn mirror_anchor(&self, dir: OrdDir) {
+		if self.mirroring.replace(true) {
+			return;
+		}
+		let anchor = self.reality_anchor.get();
+		let characters = self.characters.borrow().clone();
+		for piece in &characters {
+			let (piece_id, species) = {
+				let piece = piece.borrow();
+				(piece.id, piece.species.clone())
+			};
+			let Species::Mirror(axis) = species else {
+				continue;
+			};
+			// Earlier moves may have removed this piece.
+			if piece_id != anchor && self.get_character(piece_id).is_some() {
+				let _ = self.move_piece(piece, axis.reflect(dir));
+			}
+		}
+		self.mirroring.set(false);
+	}
+
+	/// Move every patrolling piece one step along its route.
+	///
+	/// Patrols move like anything else, so they collide, push and fire contingencies.
+	pub fn step_patrols(&self) {
+		let characters = self.characters.borrow().clone();
+		for piece in &characters {
+			let (id, (x, y, z), patrol) = {
+				let piece = piece.borrow();
+				(piece.id, (piece.x, piece.y, piece.z), piece.patrol.clone())
+			};
+			// Earlier steps may have removed this piece.
+			if self.get_character(id).is_none() {
+				continue;
+			}
+			match patrol {
+				Some(Patrol::Waypoints(mut waypoints)) => {
+					if waypoints.first() == Some(&(x, y)) {
+						waypoints.rotate_left(1);
+						piece.borrow_mut().patrol = Some(Patrol::Waypoints(waypoints.clone()));
+					}
+					let Some(&(wx, wy)) = waypoints.first() else {
+						continue;
+					};
+					if (wx, wy) == (x, y) {
+						// A single waypoint, which was already reached.
+						continue;
+					}
+					let goal = (wx, wy, z);
+					let step = match pathfind::first_step(self, (x, y, z), &[goal]) {
+						Some(step) => step,
+						// Next to the waypoint, the last step is onto it.
+						None if pathfind::wrapped_distance((x, y, z), goal) == 1 => goal,
+						// Boxed in, so wait for the way to clear.
+						None => continue,
+					};
+					let _ = self.teleport_piece(piece, step.0, step.1, step.2);
+				}
+				Some(Patrol::Bounce(dir)) => {
+					// Turn around when blocked, and try the other way.
+					let blocked = self.move_piece(piece, dir).is_err();
+					if blocked {
+						piece.borrow_mut().patrol = Some(Patrol::Bounce(dir.opposite()));
+						let _ = self.move_piece(piece, dir.opposite());
+					}
+				}
+				None => (),
+			}
+		}
+	}
+
+	/// Move each of `segments` onto the tile left by the one ahead of it, starting with `vacated`,
+	/// then fire the contingencies of the tiles they entered.
+	///
+	/// Each tile was just left, so segments are never blocked. Missing segments are skipped, closing the gap.
+	fn drag_segments(&self, segments: &[EntityId], mut vacated: (i32, i32, i32)) {
+		let mut dragged = Vec::new();
+		for id in segments {
+			let Some(segment) = self.get_character(*id) else {
+				continue;
+			};
+			if self
+				.get_character_at(vacated.0, vacated.1, vacated.2)
+				.is_some()
+			{
+				// Something else took the tile, which only contingencies could have done.
+				break;
+			}
+			let previous = self.place(&segment, vacated);
+			dragged.push((segment, vacated));
+			vacated = previous;
+		}
+		for (segment, tile) in dragged {
+			trigger_tile_entry(self, tile, &segment);
+		}
+	}
+
+	/// Move `piece` onto the free tile `to`, facing the way it moved, and return where it was.
+	///
+	/// Unlike `teleport_piece`, this does not drag segments or trigger anything.
+	fn place(&self, piece: &CharacterRef, to: (i32, i32, i32)) -> (i32, i32, i32) {
+		let mut character = piece.borrow_mut();
+		let from = (character.x, character.y, character.z);
+		(character.x, character.y, character.z) = to;
+		if let Some(momentum) = OrdDir::from_offset(wrapped_offset(from, to)) {
+			character.momentum = momentum;
+		}
+		drop(character);
+		let mut occupancy = self.occupancy.borrow_mut();
+		occupancy.remove(piece, from.0, from.1, from.2);
+		occupancy.insert(piece);
+		from
+	}
+
+	/// Push `first`, which stands on `tile`, one tile further along `pusher`'s move, along with
+	/// every pushable piece lined up behind it.
+	///
+	/// Only single steps push. Nothing moves if the row ends against a piece that can't be pushed.
+	/// Otherwise the whole row moves, and the pushed pieces are returned along with their species,
+	/// so that their contingencies can be fired once `tile` is taken.
+	fn push(
+		&self,
+		pusher: &CharacterRef,
+		first: &CharacterRef,
+		tile: (i32, i32, i32),
+	) -> Option<Vec<(CharacterRef, Species)>> {
+		let (offset, pusher_z) = {
+			let pusher = pusher.borrow();
+			(
+				wrapped_offset((pusher.x, pusher.y, pusher.z), tile),
+				pusher.z,
+			)
+		};
+		if offset == (0, 0) || offset.0.abs() > 1 || offset.1.abs() > 1 || pusher_z != tile.2 {
+			return None;
+		}
+		// Check the whole row before moving any of it.
+		let mut chain = Vec::new();
+		let mut next = tile;
+		let mut piece = first.clone();
+		loop {
+			if !piece.borrow().species.is_pushable() || std::rc::Rc::ptr_eq(&piece, pusher) {
+				return None;
+			}
+			let destination = map_wrap(next.0 + offset.0, next.1 + offset.1, next.2);
+			if destination == tile {
+				// The row goes all the way around the map, and has nowhere to go.
+				return None;
+			}
+			chain.push((piece, destination));
+			next = destination;
+			match self.get_character_at(next.0, next.1, next.2) {
+				Some(behind) => piece = behind,
+				None => break,
+			}
+		}
+		// The far end moves first, so that every piece steps onto a free tile.
+		let mut pushed = Vec::new();
+		for (piece, destination) in chain.into_iter().rev() {
+			self.place(&piece, destination);
+			let species = piece.borrow().species.clone();
+			pushed.push((piece, species));
+		}
+		Some(pushed)
+	}
+}
+
+/// Find the shortest `(dx, dy)` leading from `from` to `to`, which may cross the map's edges.
+pub fn wrapped_offset(from: (i32, i32, i32), to: (i32, i32, i32)) -> (i32, i32) {
+	let wrap = |delta: i32, size: i32| {
+		let delta = delta.rem_euclid(size);
+		if delta > size / 2 {
+			delta - size
+		} else {
+			delta
+		}
+	};
+	(
+		wrap(to.0 - from.0, WORLD_COLS as i32),
+		wrap(to.1 - from.1, WORLD_ROWS as i32),
+	)
 }
 
 pub fn map_wrap(x: i32, y: i32, z: i32) -> (i32, i32, i32) {